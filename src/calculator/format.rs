@@ -0,0 +1,125 @@
+use super::{get_tokens, Node, Operation, Parser, Token};
+
+fn precedence(op: &Operation) -> u8 {
+    match op {
+        Operation::Plus | Operation::Minus => 1,
+        Operation::Mul | Operation::Div => 2,
+    }
+}
+
+fn op_text(op: &Operation) -> &'static str {
+    match op {
+        Operation::Plus => "+",
+        Operation::Minus => "-",
+        Operation::Mul => "*",
+        Operation::Div => "/",
+    }
+}
+
+fn format_string(s: &str) -> String {
+    let mut text = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => text += "\\\"",
+            '\\' => text += "\\\\",
+            '\n' => text += "\\n",
+            _ => text += &String::from(ch),
+        }
+    }
+    text += "\"";
+    return text;
+}
+
+///Cell references are upper cased, eg: $a1 becomes $A1
+fn format_ident(ident: &str) -> String {
+    if ident.starts_with('$') {
+        return ident.to_uppercase();
+    }
+    //references to other sheets keep the sheet's name as written, eg: rates!$B4
+    if let Some((sheet, cell)) = ident.split_once('!') {
+        return format!("{}!{}", sheet, format_ident(cell));
+    }
+    return ident.to_string();
+}
+
+fn format_operand(node: &Node, parent: &Operation, is_right: bool) -> String {
+    let text = node.format();
+    if let Node::BinOp(_, op, _) = node {
+        //left associative, so an operand on the right with the same precedence needs parentheses
+        if precedence(op) < precedence(parent) || (is_right && precedence(op) == precedence(parent))
+        {
+            return format!("({})", text);
+        }
+    }
+    return text;
+}
+
+impl Node {
+    ///Prints the tree as canonical formula text
+    pub fn format(&self) -> String {
+        match self {
+            Node::Number(n) => n.to_string(),
+            Node::Quantity(n, unit) => format!("{} {}", n, unit),
+            Node::String(s) => format_string(s),
+            Node::Ident(i) => format_ident(i),
+            Node::Range(start, end) => format!("{}:{}", format_ident(start), format_ident(end)),
+            Node::Call(name, args) => format!(
+                "{}({})",
+                name.to_uppercase(),
+                args.iter()
+                    .map(|a| a.format())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Node::Neg(node) => match **node {
+                Node::BinOp(..) => format!("-({})", node.format()),
+                _ => format!("-{}", node.format()),
+            },
+            Node::BinOp(left, op, right) => format!(
+                "{} {} {}",
+                format_operand(left, op, false),
+                op_text(op),
+                format_operand(right, op, true)
+            ),
+        }
+    }
+}
+
+///Formats equation text, returns None if the equation cannot be fully parsed or has characters
+///that are not part of the formula syntax
+pub fn format_equation(equation: &str) -> Option<String> {
+    let tokens = get_tokens(equation);
+    if tokens.len() == 0 {
+        return None;
+    }
+    if tokens.iter().any(|t| matches!(t, Token::Unknown)) {
+        return None;
+    }
+    let mut parser = Parser::new(tokens);
+    let tree = parser.build_tree();
+    if !parser.is_done() {
+        return None;
+    }
+    return Some(tree.format());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_equation;
+
+    #[test]
+    fn keeps_unary_minus() {
+        assert_eq!(format_equation("-$a1"), Some("-$A1".to_string()));
+        assert_eq!(format_equation("2*-$a1"), Some("2 * -$A1".to_string()));
+        assert_eq!(format_equation("-(1+$a1)"), Some("-(1 + $A1)".to_string()));
+        assert_eq!(format_equation("1 - -2"), Some("1 - -2".to_string()));
+    }
+
+    #[test]
+    fn formatted_text_formats_to_itself() {
+        for equation in ["-$a1", "-sum($a1:$b2)*3", "($a1-1)/-2", "1-(2-3)"] {
+            let text = format_equation(equation).unwrap();
+            assert_eq!(format_equation(&text), Some(text.clone()));
+        }
+    }
+}
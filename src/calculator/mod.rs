@@ -1,13 +1,33 @@
-use std::{collections::HashMap, str::Chars};
+use std::{collections::HashMap, rc::Rc, str::Chars};
 
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::decimal::{self, Decimal};
 use crate::table::{self, Data, Position, Table};
+use crate::workbook::{FoundSheet, Workbook};
+
+pub mod format;
+pub mod trace;
+pub mod units;
+
+use trace::Trace;
+use units::Unit;
 
 static RECURSION_LIMIT: f64 = 1000.0;
+///The most values randarray makes, as it is made again on every recalculation
+static MAX_ARRAY_LEN: f64 = 100000.0;
 
 #[derive(Debug)]
 pub enum CalculatorError {
     RecursionLimit,
-    InvalidBinaryOp(Operation)
+    InvalidBinaryOp(Operation),
+    DivisionByZero,
+    Overflow,
+    UnitMismatch,
+    UnknownUnit,
+    UnknownSheet,
+    InvalidArgument,
+    CircularReference,
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +44,11 @@ pub enum Token {
     Colon,
     Comma,
     Eq,
+    Unit(String),
+    ///A character that is not part of the formula syntax, evaluated as 0
+    Unknown,
+    ///After the last token
+    End,
 }
 
 #[derive(Debug)]
@@ -69,21 +94,17 @@ impl Lexer<'_> {
     }
 
     fn build_string(&mut self) -> String {
-        if let None = self.next() {
-            return String::new();
-        }
-        let mut text = String::from(self.cur_char.unwrap());
+        let mut text = String::new();
         let mut escape = false;
         while let Some(ch) = self.next() {
-            if ch == '\\' {
-                escape = true;
-                continue;
-            } else if escape {
+            if escape {
                 text += &String::from(match ch {
                     'n' => '\n',
                     _ => ch,
                 });
                 escape = false;
+            } else if ch == '\\' {
+                escape = true;
             } else if ch == '"' {
                 break;
             } else {
@@ -93,6 +114,50 @@ impl Lexer<'_> {
         return text;
     }
 
+    ///Reads a unit such as `mm` or `m/s^2` directly after a number. A word that is not a unit is
+    ///still read as one so that the equation fails with an unknown unit instead of ignoring it
+    fn build_unit(&mut self) -> Option<String> {
+        while let Some(' ' | '\t') = self.cur_char {
+            self.next();
+        }
+        let first = self.cur_char?;
+        if !first.is_ascii_alphabetic() {
+            return None;
+        }
+        //a - is only part of the unit as the sign of an exponent, eg: m*s^-2
+        let mut candidate = String::new();
+        for c in std::iter::once(first).chain(self.chars.clone()) {
+            let is_sign = c == '-' && candidate.ends_with('^');
+            if !c.is_ascii_alphanumeric() && !"^*/".contains(c) && !is_sign {
+                break;
+            }
+            candidate.push(c);
+        }
+        //use the longest part that ends before a * or / that is a unit
+        let mut ends: Vec<usize> = candidate
+            .char_indices()
+            .filter(|(_, c)| *c == '*' || *c == '/')
+            .map(|(i, _)| i)
+            .collect();
+        ends.push(candidate.len());
+        let unit = ends
+            .into_iter()
+            .rev()
+            .map(|end| &candidate[0..end])
+            .find(|text| Unit::parse(text).is_some())
+            .unwrap_or_else(|| {
+                let end = candidate
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(candidate.len());
+                &candidate[0..end]
+            })
+            .to_string();
+        for _ in 0..unit.len() {
+            self.next();
+        }
+        return Some(unit);
+    }
+
     fn build_ident(&mut self) -> String {
         let mut ident = self.cur_char.unwrap().to_string();
         while let Some(ch) = self.next() {
@@ -132,6 +197,9 @@ impl Lexer<'_> {
                     '0'..='9' => {
                         let tok = Token::Number(self.build_number());
                         tokens.push(tok);
+                        if let Some(unit) = self.build_unit() {
+                            tokens.push(Token::Unit(unit));
+                        }
                         continue;
                     }
                     ' ' | '\t' | '\n' => {
@@ -139,11 +207,20 @@ impl Lexer<'_> {
                         continue;
                     }
                     'A'..='Z' | 'a'..='z' | '_' => {
-                        let tok = Token::Ident(self.build_ident());
+                        let mut ident = self.build_ident();
+                        //a reference to a cell in another sheet, eg: rates!$B4
+                        if let Some('!') = self.cur_char {
+                            self.next();
+                            if let Some('$') = self.cur_char {
+                                ident += "!";
+                                ident += &self.build_ident();
+                            }
+                        }
+                        let tok = Token::Ident(ident);
                         tokens.push(tok);
                         continue;
                     }
-                    _ => Token::Number(0.0),
+                    _ => Token::Unknown,
                 };
                 tokens.push(tok);
             }
@@ -169,105 +246,246 @@ pub enum Node {
     Ident(String),
     Range(String, String),
     Call(String, Vec<Node>),
+    Quantity(f64, String),
+    Neg(Box<Node>),
 }
 
 impl Node {
+    ///Evaluates the node, when a trace is given each sub expression is recorded in it
     pub fn visit(
         &self,
         symbols: &mut HashMap<String, CalculatorValue>,
-        table: &Table,
+        book: &Workbook,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<CalculatorValue, CalculatorError> {
+        let line = trace.as_deref_mut().map(|t| t.begin());
+        let result = self.visit_node(symbols, book, trace.as_deref_mut());
+        if let (Some(t), Some(line)) = (trace, line) {
+            t.finish(line, &self.format(), &result);
+        }
+        return result;
+    }
+
+    fn visit_node(
+        &self,
+        symbols: &mut HashMap<String, CalculatorValue>,
+        book: &Workbook,
+        mut trace: Option<&mut Trace>,
     ) -> Result<CalculatorValue, CalculatorError> {
         match self {
             Node::Call(fn_name, nodes) => {
+                let fn_name = fn_name.to_lowercase();
                 let mut values: Vec<CalculatorValue> = vec![];
                 //not using map because closures require a special borrow that breaks everything
                 for node in nodes {
-                    if let Ok(v) = node.visit(symbols, table) {
-                        values.push(v);
+                    match node.visit(symbols, book, trace.as_deref_mut()) {
+                        Ok(v) => values.push(v),
+                        Err(CalculatorError::CircularReference) => {
+                            return Err(CalculatorError::CircularReference)
+                        }
+                        Err(_) => {}
                     }
                 }
                 if fn_name == "rand" {
-                    return Ok(CalculatorValue::Number(rand::random()));
-                } else if fn_name == "sum" {
-                    let mut sum = 0.0;
+                    let mut rng = volatile_rng(symbols, book);
+                    return Ok(CalculatorValue::Number(rng.gen()));
+                } else if fn_name == "randbetween" {
+                    let low = values.first().map_or(0.0, |v| v.to_f64(symbols, book)).ceil();
+                    let high = values.get(1).map_or(0.0, |v| v.to_f64(symbols, book)).floor();
+                    let mut rng = volatile_rng(symbols, book);
+                    if high < low {
+                        return Ok(CalculatorValue::Number(low));
+                    }
+                    return Ok(CalculatorValue::Number(
+                        rng.gen_range(low as i64..=high as i64) as f64,
+                    ));
+                } else if fn_name == "randarray" {
+                    let count = values.first().map_or(1.0, |v| v.to_f64(symbols, book));
+                    if !(0.0..=MAX_ARRAY_LEN).contains(&count) {
+                        return Err(CalculatorError::InvalidArgument);
+                    }
+                    let low = values.get(1).map_or(0.0, |v| v.to_f64(symbols, book));
+                    let high = values.get(2).map_or(1.0, |v| v.to_f64(symbols, book));
+                    let mut rng = volatile_rng(symbols, book);
+                    let mut items: Vec<CalculatorValue> = vec![];
+                    for _ in 0..(count as usize) {
+                        items.push(CalculatorValue::Number(low + rng.gen::<f64>() * (high - low)));
+                    }
+                    return Ok(CalculatorValue::Array(items));
+                } else if fn_name == "shuffle" {
+                    let mut items: Vec<CalculatorValue> = vec![];
                     for value in values {
-                        sum += value.to_f64(symbols, table);
+                        items.append(&mut value.to_values(symbols, book));
                     }
-                    return Ok(CalculatorValue::Number(sum));
-                } else if fn_name == "mean" {
-                    let mut sum = 0.0;
-                    let mut real_count = 0; //dont count strings
+                    let mut rng = volatile_rng(symbols, book);
+                    items.shuffle(&mut rng);
+                    return Ok(CalculatorValue::Array(items));
+                } else if fn_name == "convert" {
+                    let target = match values.get(1) {
+                        Some(CalculatorValue::String(s)) => {
+                            Unit::parse(s).ok_or(CalculatorError::UnknownUnit)?
+                        }
+                        _ => return Err(CalculatorError::UnknownUnit),
+                    };
+                    let (n, unit) = match values.first() {
+                        Some(CalculatorValue::Quantity(n, u)) => (*n, u.clone()),
+                        Some(v) => (v.to_f64(symbols, book), Unit::none()),
+                        None => return Err(CalculatorError::UnitMismatch),
+                    };
+                    let converted = unit
+                        .convert(n, &target)
+                        .ok_or(CalculatorError::UnitMismatch)?;
+                    return Ok(CalculatorValue::Quantity(converted, target));
+                } else if fn_name == "sum" || fn_name == "mean" {
+                    let mut items: Vec<CalculatorValue> = vec![];
                     for value in values {
-                        match value {
-                            CalculatorValue::Number(n) => {
-                                sum += n;
-                                real_count += 1;
-                            }
-                            CalculatorValue::Range(start, end) => {
-                                let vals = table.get_values_at_range(&start, &end);
-                                for val in vals {
-                                    match val {
-                                        table::Data::Number(n) => {
-                                            sum += n.parse().unwrap_or(0.0);
-                                            real_count += 1;
-                                        }
-                                        table::Data::Equation(e, cache) => {
-                                            if let Some(r) = cache {
-                                                sum += r.to_f64(symbols, table);
-                                            } else {
-                                                sum += calculate(&e, symbols, table)?
-                                                    .to_f64(symbols, table);
-                                            }
-                                            real_count += 1;
-                                        }
-                                        _ => {}
-                                    }
-                                }
+                        items.append(&mut value.to_values(symbols, book));
+                    }
+                    let has_units = items
+                        .iter()
+                        .any(|i| matches!(i, CalculatorValue::Quantity(..)));
+                    let total = if has_units {
+                        quantity_sum(&items, symbols, book)?
+                    } else if current_sheet(symbols, book).is_decimal() {
+                        let mut sum = Decimal::zero();
+                        for item in &items {
+                            if let Some(d) = item.to_decimal() {
+                                sum = sum.add(&d).ok_or(CalculatorError::Overflow)?;
                             }
-                            _ => {}
                         }
+                        CalculatorValue::Decimal(sum)
+                    } else {
+                        let mut sum = 0.0;
+                        for item in &items {
+                            sum += item.to_f64(symbols, book);
+                        }
+                        CalculatorValue::Number(sum)
+                    };
+                    if fn_name == "sum" {
+                        return Ok(total);
                     }
-                    return Ok(CalculatorValue::Number(sum / real_count as f64));
+                    //dont count strings
+                    let real_count = items
+                        .iter()
+                        .filter(|i| !matches!(i, CalculatorValue::String(..)))
+                        .count();
+                    return match total {
+                        CalculatorValue::Decimal(sum) => {
+                            let count =
+                                Decimal::parse(&real_count.to_string()).unwrap_or(Decimal::zero());
+                            decimal_op(&sum, &Operation::Div, &count, current_sheet(symbols, book))
+                        }
+                        CalculatorValue::Quantity(sum, unit) => {
+                            Ok(CalculatorValue::Quantity(sum / real_count as f64, unit))
+                        }
+                        _ => {
+                            let sum = total.to_f64(symbols, book);
+                            Ok(CalculatorValue::Number(sum / real_count as f64))
+                        }
+                    };
                 }
                 return Ok(CalculatorValue::Number(0.0));
             }
             Node::Ident(s) => {
+                if let Some((sheet, cell)) = s.split_once('!') {
+                    let node = Node::Ident(cell.to_string());
+                    return visit_in_sheet(sheet, &node, symbols, book, trace);
+                }
                 if s.chars().nth(0).unwrap_or('a') != '$' {
-                    Ok(symbols
-                        .get(s)
-                        .unwrap_or(&CalculatorValue::Number(0.0))
-                        .to_owned())
+                    if let Some(value) = symbols.get(s) {
+                        return Ok(value.to_owned());
+                    }
+                    //named ranges of one cell are used like a reference to that cell
+                    let sheet = current_sheet(symbols, book);
+                    if let Some(range) = sheet.get_settings().find_named_range(s) {
+                        if range.start == range.end {
+                            let cell = format!("${}", table::position_name(&range.start));
+                            return Node::Ident(cell).visit(symbols, book, trace);
+                        }
+                        return Ok(CalculatorValue::Range(range.start, range.end));
+                    }
+                    Ok(CalculatorValue::Number(0.0))
                 } else {
                     let name = s[1..].to_string();
-                    let pos = table.human_position_to_position(name);
-                    let val = table.get_value_at_position(&pos);
+                    let sheet = current_sheet(symbols, book);
+                    let pos = sheet.human_position_to_position(name);
+                    let val = sheet.get_value_at_position(&pos);
                     let res_value = match val {
                         Data::Number(n) => {
+                            if sheet.is_decimal() {
+                                if let Some(d) = Decimal::parse(&n) {
+                                    return Ok(CalculatorValue::Decimal(d));
+                                }
+                            }
                             let num: f64 = n.parse().unwrap();
                             return Ok(CalculatorValue::Number(num));
                         }
                         Data::String(a) => CalculatorValue::String(a),
-                        Data::Equation(e, _cache) => {
-                            //FIXME: can be infinitely recursive when self referencing occurs
-                            return calculate(&e, symbols, table);
+                        Data::Equation(_, cache, tree) => {
+                            //in iterative mode references read the previous iteration's value
+                            //so that circular references do not recurse
+                            if sheet.is_iterative() {
+                                return Ok(cache.unwrap_or(CalculatorValue::Number(0.0)));
+                            }
+                            return calculate_cell(&pos, &tree, symbols, book, trace);
                         }
                     };
                     return Ok(res_value);
                 }
             }
             Node::Range(start, finish) => {
-                let start_pos = table.human_position_to_position(start[1..].to_owned());
-                let end_pos = table.human_position_to_position(finish[1..].to_owned());
-                Ok(CalculatorValue::Range(start_pos, end_pos))
+                //ranges in other sheets are read as the values in them
+                if let Some((sheet, start)) = start.split_once('!') {
+                    let finish = finish.split_once('!').map_or(finish.as_str(), |f| f.1);
+                    let range = Node::Range(start.to_string(), finish.to_string());
+                    return visit_in_sheet(sheet, &range, symbols, book, trace);
+                }
+                let sheet = current_sheet(symbols, book);
+                let start_pos = sheet.human_position_to_position(start[1..].to_owned());
+                let end_pos = sheet.human_position_to_position(finish[1..].to_owned());
+                let range = CalculatorValue::Range(start_pos, end_pos);
+                if let Some(t) = trace {
+                    let cells = current_sheet(symbols, book).get_cells_at_range(&start_pos, &end_pos);
+                    let values = range.to_values(symbols, book);
+                    for ((pos, _), value) in cells.iter().zip(values) {
+                        t.record(&format!(
+                            "{} = {}",
+                            table::position_name(pos),
+                            table::format_value(&value)
+                        ));
+                    }
+                }
+                Ok(range)
             }
-            Node::Number(n) => Ok(CalculatorValue::Number(n.to_owned())),
+            Node::Number(n) => match Decimal::from_f64(*n) {
+                Some(d) if current_sheet(symbols, book).is_decimal() => Ok(CalculatorValue::Decimal(d)),
+                _ => Ok(CalculatorValue::Number(n.to_owned())),
+            },
             Node::String(s) => Ok(CalculatorValue::String(s.to_string())),
+            Node::Quantity(n, unit) => match Unit::parse(unit) {
+                Some(u) => Ok(CalculatorValue::Quantity(*n, u)),
+                None => Err(CalculatorError::UnknownUnit),
+            },
+            Node::Neg(node) => match node.visit(symbols, book, trace)? {
+                CalculatorValue::Number(n) => Ok(CalculatorValue::Number(-n)),
+                CalculatorValue::Quantity(n, unit) => Ok(CalculatorValue::Quantity(-n, unit)),
+                CalculatorValue::Decimal(d) => Decimal::zero()
+                    .sub(&d)
+                    .map(CalculatorValue::Decimal)
+                    .ok_or(CalculatorError::Overflow),
+                _ => Err(CalculatorError::InvalidBinaryOp(Operation::Minus)),
+            },
             Node::BinOp(left, op, right) => {
-                let left_val = left.visit(symbols, table);
-                let right_val = right.visit(symbols, table);
+                let left_val = left.visit(symbols, book, trace.as_deref_mut());
+                let right_val = right.visit(symbols, book, trace);
 
                 match (left_val, right_val) {
+                    (Err(e @ (CalculatorError::UnitMismatch | CalculatorError::UnknownUnit | CalculatorError::CircularReference)), _)
+                    | (_, Err(e @ (CalculatorError::UnitMismatch | CalculatorError::UnknownUnit | CalculatorError::CircularReference))) => Err(e),
                     (Ok(left), Ok(right)) => match (left, right) {
+                        (CalculatorValue::Quantity(n, u), CalculatorValue::Quantity(n2, u2)) => quantity_op(n, &u, op, n2, &u2),
+                        (CalculatorValue::Quantity(n, u), right @ (CalculatorValue::Number(..) | CalculatorValue::Decimal(..))) => quantity_op(n, &u, op, right.to_f64(symbols, book), &Unit::none()),
+                        (left @ (CalculatorValue::Number(..) | CalculatorValue::Decimal(..)), CalculatorValue::Quantity(n2, u2)) => quantity_op(left.to_f64(symbols, book), &Unit::none(), op, n2, &u2),
                         (CalculatorValue::Number(n), CalculatorValue::Number(n2)) => Ok(CalculatorValue::Number(match op {
                             Operation::Mul => n*n2,
                             Operation::Div => n / n2,
@@ -278,20 +496,17 @@ impl Node {
                             Operation::Plus => s + &s2,
                             _ => "".to_owned()
                         })),
-                        (CalculatorValue::String(s), CalculatorValue::Number(n)) => Ok(CalculatorValue::String(match op {
-                            Operation::Mul => {
-                                if n < 1.0 {
-                                    "".to_string()
-                                } else {
-                                    let mut text = s.clone();
-                                    for _ in 1..(n as i32) {
-                                        text += &s;
-                                    }
-                                    text
-                                }
-                            },
-                            _ => s
-                        })),
+                        (CalculatorValue::String(s), CalculatorValue::Number(n)) => Ok(repeat_string(s, op, n)),
+                        (CalculatorValue::String(s), CalculatorValue::Decimal(n)) => Ok(repeat_string(s, op, n.to_f64())),
+                        (CalculatorValue::Decimal(a), CalculatorValue::Decimal(b)) => decimal_op(&a, op, &b, current_sheet(symbols, book)),
+                        (CalculatorValue::Decimal(a), CalculatorValue::Number(b)) => {
+                            let b = Decimal::from_f64(b).ok_or(CalculatorError::Overflow)?;
+                            decimal_op(&a, op, &b, current_sheet(symbols, book))
+                        }
+                        (CalculatorValue::Number(a), CalculatorValue::Decimal(b)) => {
+                            let a = Decimal::from_f64(a).ok_or(CalculatorError::Overflow)?;
+                            decimal_op(&a, op, &b, current_sheet(symbols, book))
+                        }
                         _ => Err(CalculatorError::InvalidBinaryOp(*op))
                     }
                     _ => Ok(CalculatorValue::Number(0.0)),
@@ -301,6 +516,113 @@ impl Node {
     }
 }
 
+///Arithmetic on numbers with units, + and - require the same dimensions and the result is in the
+///left unit
+fn quantity_op(
+    n: f64,
+    unit: &Unit,
+    op: &Operation,
+    n2: f64,
+    unit2: &Unit,
+) -> Result<CalculatorValue, CalculatorError> {
+    let (value, unit) = match op {
+        Operation::Plus | Operation::Minus => {
+            let n2 = unit2
+                .convert(n2, unit)
+                .ok_or(CalculatorError::UnitMismatch)?;
+            match op {
+                Operation::Plus => (n + n2, unit.clone()),
+                _ => (n - n2, unit.clone()),
+            }
+        }
+        Operation::Mul => (n * n2, unit.mul(unit2)),
+        Operation::Div => (n / n2, unit.div(unit2)),
+    };
+    if unit.is_dimensionless() {
+        return Ok(CalculatorValue::Number(value * unit.factor()));
+    }
+    return Ok(CalculatorValue::Quantity(value, unit));
+}
+
+///Adds values with `+`, so units are converted to the first value's unit and values of other
+///dimensions are an error
+fn quantity_sum(
+    items: &[CalculatorValue],
+    symbols: &mut HashMap<String, CalculatorValue>,
+    book: &Workbook,
+) -> Result<CalculatorValue, CalculatorError> {
+    let mut total: Option<(f64, Unit)> = None;
+    for item in items {
+        let (n, unit) = match item {
+            CalculatorValue::Quantity(n, unit) => (*n, unit.clone()),
+            CalculatorValue::Number(..) | CalculatorValue::Decimal(..) => {
+                (item.to_f64(symbols, book), Unit::none())
+            }
+            _ => continue,
+        };
+        total = Some(match total {
+            None => (n, unit),
+            Some((sum, sum_unit)) => {
+                match quantity_op(sum, &sum_unit, &Operation::Plus, n, &unit)? {
+                    CalculatorValue::Quantity(sum, sum_unit) => (sum, sum_unit),
+                    sum => (sum.to_f64(symbols, book), Unit::none()),
+                }
+            }
+        });
+    }
+    return match total {
+        Some((sum, unit)) if !unit.is_dimensionless() => Ok(CalculatorValue::Quantity(sum, unit)),
+        Some((sum, unit)) => Ok(CalculatorValue::Number(sum * unit.factor())),
+        None => Ok(CalculatorValue::Number(0.0)),
+    };
+}
+
+fn repeat_string(s: String, op: &Operation, n: f64) -> CalculatorValue {
+    CalculatorValue::String(match op {
+        Operation::Mul => {
+            if n < 1.0 {
+                "".to_string()
+            } else {
+                let mut text = s.clone();
+                for _ in 1..(n as i32) {
+                    text += &s;
+                }
+                text
+            }
+        }
+        _ => s,
+    })
+}
+
+///Exact base 10 arithmetic, division keeps as many digits as fit
+fn decimal_op(
+    a: &Decimal,
+    op: &Operation,
+    b: &Decimal,
+    sheet: &Table,
+) -> Result<CalculatorValue, CalculatorError> {
+    let result = match op {
+        Operation::Plus => a.add(b),
+        Operation::Minus => a.sub(b),
+        Operation::Mul => a.mul(b),
+        Operation::Div => {
+            if b.is_zero() {
+                return Err(CalculatorError::DivisionByZero);
+            }
+            let rounding = sheet.get_settings().decimal.map_or(
+                decimal::RoundingMode::HalfEven,
+                |d| d.rounding,
+            );
+            (0..=decimal::EXACT_DIVISION_PLACES)
+                .rev()
+                .find_map(|places| a.div(b, places, rounding))
+        }
+    };
+    return result
+        .map(CalculatorValue::Decimal)
+        .ok_or(CalculatorError::Overflow);
+}
+
 #[derive(Debug)]
 pub struct Parser {
     cur_pos: usize,
@@ -320,6 +642,7 @@ impl Parser {
     fn next(&mut self) -> bool {
         self.cur_pos += 1;
         if self.cur_pos >= self.tokens.len() {
+            self.cur_tok = Token::End;
             return false;
         }
         self.cur_tok = self.tokens[self.cur_pos].clone();
@@ -330,6 +653,10 @@ impl Parser {
         match self.cur_tok.clone() {
             Token::Number(n) => {
                 self.next();
+                if let Token::Unit(unit) = self.cur_tok.clone() {
+                    self.next();
+                    return Node::Quantity(n, unit);
+                }
                 return Node::Number(n);
             }
             Token::String(s) => {
@@ -369,6 +696,10 @@ impl Parser {
                 self.next();
                 return node;
             }
+            Token::Minus => {
+                self.next();
+                return Node::Neg(Box::new(self.factor()));
+            }
             _ => Node::Number(0.0),
         }
     }
@@ -413,6 +744,11 @@ impl Parser {
     pub fn build_tree(&mut self) -> Node {
         return self.expr();
     }
+
+    ///Whether every token was used while building the tree
+    pub fn is_done(&self) -> bool {
+        return self.cur_pos >= self.tokens.len();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -420,26 +756,38 @@ pub enum CalculatorValue {
     String(String),
     Number(f64),
     Range(Position, Position),
+    Array(Vec<CalculatorValue>),
+    Decimal(Decimal),
+    Quantity(f64, Unit),
 }
 
 impl CalculatorValue {
-    pub fn to_f64(&self, symbols: &mut HashMap<String, CalculatorValue>, table: &Table) -> f64 {
+    pub fn to_f64(&self, symbols: &mut HashMap<String, CalculatorValue>, book: &Workbook) -> f64 {
         match self {
             CalculatorValue::String(..) => 0.0,
             CalculatorValue::Number(n) => *n,
+            CalculatorValue::Decimal(d) => d.to_f64(),
+            CalculatorValue::Quantity(n, _) => *n,
+            CalculatorValue::Array(items) => {
+                let mut sum = 0.0;
+                for item in items {
+                    sum += item.to_f64(symbols, book);
+                }
+                return sum;
+            }
             CalculatorValue::Range(start, end) => {
-                let vals = table.get_values_at_range(&start, &end);
+                let cells = current_sheet(symbols, book).get_cells_at_range(start, end);
                 let mut sum = 0.0;
-                for val in vals {
+                for (pos, val) in cells {
                     match val {
                         table::Data::String(..) => sum += 0.0,
                         table::Data::Number(n) => sum += n.parse().unwrap_or(0.0),
-                        table::Data::Equation(e, cache) => {
+                        table::Data::Equation(_, cache, tree) => {
                             if let Some(r) = cache {
-                                sum += r.to_f64(symbols, table);
+                                sum += r.to_f64(symbols, book);
                             } else {
-                                if let Ok(v) = calculate(&e, symbols, table) {
-                                    sum += v.to_f64(symbols, table);
+                                if let Ok(v) = calculate_cell(&pos, &tree, symbols, book, None) {
+                                    sum += v.to_f64(symbols, book);
                                 }
                             }
                         }
@@ -449,56 +797,283 @@ impl CalculatorValue {
             }
         }
     }
-}
-
-pub struct Interpreter {
-    tree: Node,
-}
 
-impl Interpreter {
-    pub fn new(tree: Node) -> Interpreter {
-        return Interpreter { tree };
+    pub fn to_decimal(&self) -> Option<Decimal> {
+        match self {
+            CalculatorValue::Decimal(d) => Some(*d),
+            CalculatorValue::Number(n) => Decimal::from_f64(*n),
+            _ => None,
+        }
     }
 
-    pub fn interpret(
+    ///Flattens ranges and arrays into a list of single values
+    pub fn to_values(
         &self,
         symbols: &mut HashMap<String, CalculatorValue>,
-        table: &Table,
-    ) -> Result<CalculatorValue, CalculatorError> {
-        let rec_count = symbols.get_mut("%recursion");
-        if let Some(CalculatorValue::Number(n)) = rec_count {
-            *n += 1.0;
-            if *n > RECURSION_LIMIT {
-                return Err(CalculatorError::RecursionLimit);
+        book: &Workbook,
+    ) -> Vec<CalculatorValue> {
+        match self {
+            CalculatorValue::Array(items) => items.clone(),
+            CalculatorValue::Range(start, end) => {
+                let mut items: Vec<CalculatorValue> = vec![];
+                for (pos, val) in current_sheet(symbols, book).get_cells_at_range(start, end) {
+                    items.push(match val {
+                        table::Data::String(s) => CalculatorValue::String(s),
+                        table::Data::Number(n) => match Decimal::parse(&n) {
+                            Some(d) if current_sheet(symbols, book).is_decimal() => CalculatorValue::Decimal(d),
+                            _ => CalculatorValue::Number(n.parse().unwrap_or(0.0)),
+                        },
+                        table::Data::Equation(_, cache, tree) => match cache {
+                            Some(r) => r,
+                            None => calculate_cell(&pos, &tree, symbols, book, None)
+                                .unwrap_or(CalculatorValue::Number(0.0)),
+                        },
+                    });
+                }
+                return items;
             }
+            _ => vec![self.clone()],
         }
-        self.tree.visit(symbols, table)
     }
 }
 
-pub fn get_tokens(equation: &str) -> Vec<Token> {
-    let mut lexer = Lexer::new(equation);
-    let toks = lexer.tokenize();
-    return toks;
+///Creates the symbol table used to evaluate the equation in the cell at pos
+pub fn cell_symbols(pos: &Position) -> HashMap<String, CalculatorValue> {
+    let mut map: HashMap<String, CalculatorValue> = HashMap::new();
+    map.insert("%recursion".to_string(), CalculatorValue::Number(0.0));
+    map.insert("%row".to_string(), CalculatorValue::Number(pos.row as f64));
+    map.insert("%col".to_string(), CalculatorValue::Number(pos.col as f64));
+    map.insert("%rand".to_string(), CalculatorValue::Number(0.0));
+    return map;
 }
 
-pub fn calcualte_from_tokens(
-    tokens: Vec<Token>,
+///The symbols for evaluating the cell at pos in the sheet at index in the file, rather than in
+///the sheet being edited
+pub fn sheet_cell_symbols(pos: &Position, sheet: usize) -> HashMap<String, CalculatorValue> {
+    let mut map = cell_symbols(pos);
+    map.insert("%sheet".to_string(), CalculatorValue::Number(sheet as f64));
+    return map;
+}
+
+///The index of the sheet being evaluated in, cells without a sheet name are in this sheet.
+///Without `%sheet` this is the sheet being edited
+fn sheet_index(symbols: &HashMap<String, CalculatorValue>, book: &Workbook) -> usize {
+    return match symbols.get("%sheet") {
+        Some(CalculatorValue::Number(n)) => *n as usize,
+        _ => book.active_index(),
+    };
+}
+
+///The sheet being evaluated in, its settings such as `%decimal` apply to the equation
+fn current_sheet<'a>(symbols: &HashMap<String, CalculatorValue>, book: &'a Workbook) -> &'a Table {
+    return book.sheet(sheet_index(symbols, book));
+}
+
+///Evaluates node in the sheet called name, ranges in the result are read as the values in them as
+///they cannot be used outside of that sheet
+fn visit_in_sheet(
+    name: &str,
+    node: &Node,
     symbols: &mut HashMap<String, CalculatorValue>,
-    table: &Table,
+    book: &Workbook,
+    trace: Option<&mut Trace>,
 ) -> Result<CalculatorValue, CalculatorError> {
-    let mut parser = Parser::new(tokens);
-    let tree = parser.build_tree();
-    let int = Interpreter::new(tree);
-    let val = int.interpret(symbols, table);
-    return val;
+    let found = book
+        .find_sheet(name, sheet_index(symbols, book))
+        .ok_or(CalculatorError::UnknownSheet)?;
+    let (saved, book) = match found {
+        FoundSheet::Workbook(index) => (
+            symbols.insert("%sheet".to_string(), CalculatorValue::Number(index as f64)),
+            book,
+        ),
+        //included sheets are evaluated as the sheet being edited of their own file
+        FoundSheet::Include(other) => (symbols.remove("%sheet"), other),
+    };
+    let result = match node.visit_node(symbols, book, trace) {
+        Ok(range @ CalculatorValue::Range(..)) => {
+            Ok(CalculatorValue::Array(range.to_values(symbols, book)))
+        }
+        result => result,
+    };
+    match saved {
+        Some(v) => symbols.insert("%sheet".to_string(), v),
+        None => symbols.remove("%sheet"),
+    };
+    return result;
+}
+
+///Evaluates the equation in the cell at pos, the cell being evaluated is restored afterwards
+pub fn calculate_cell(
+    pos: &Position,
+    tree: &Node,
+    symbols: &mut HashMap<String, CalculatorValue>,
+    book: &Workbook,
+    trace: Option<&mut Trace>,
+) -> Result<CalculatorValue, CalculatorError> {
+    //the cells being evaluated are marked, so a cell that refers back to itself is an error
+    //instead of recursing until the stack runs out. Included files have their own tables, so
+    //the table's address tells their cells apart
+    let evaluating = format!(
+        "%evaluating {:p} {} {}",
+        current_sheet(symbols, book),
+        pos.row,
+        pos.col
+    );
+    if symbols.contains_key(&evaluating) {
+        return Err(CalculatorError::CircularReference);
+    }
+    symbols.insert(evaluating.clone(), CalculatorValue::Number(1.0));
+    let mut saved: Vec<(String, Option<CalculatorValue>)> = vec![];
+    for (name, value) in cell_symbols(pos) {
+        if name != "%recursion" {
+            saved.push((name.clone(), symbols.insert(name, value)));
+        }
+    }
+    let result = evaluate(tree, symbols, book, trace);
+    for (name, value) in saved {
+        match value {
+            Some(v) => symbols.insert(name, v),
+            None => symbols.remove(&name),
+        };
+    }
+    symbols.remove(&evaluating);
+    return result;
+}
+
+///Random number generator for volatile functions.
+///The generator only depends on the sheet seed, the cell being evaluated and how many volatile
+///calls that cell has made, so values stay the same between renders until the seed changes
+fn volatile_rng(symbols: &mut HashMap<String, CalculatorValue>, book: &Workbook) -> StdRng {
+    let get = |name: &str| match symbols.get(name) {
+        Some(CalculatorValue::Number(n)) => *n as u64,
+        _ => 0,
+    };
+    let row = get("%row");
+    let col = get("%col");
+    let call = get("%rand");
+    let sheet = sheet_index(symbols, book) as u64;
+    symbols.insert("%rand".to_string(), CalculatorValue::Number((call + 1) as f64));
+
+    let mut seed = current_sheet(symbols, book).volatile_seed();
+    for n in [row, col, call] {
+        seed = seed.rotate_left(21) ^ n.wrapping_mul(0x9E3779B97F4A7C15);
+    }
+    //the first sheet gets the same values as a file with one sheet
+    seed ^= sheet.wrapping_mul(0xC2B2AE3D27D4EB4F);
+    return StdRng::seed_from_u64(seed);
+}
+
+pub fn evaluate(
+    tree: &Node,
+    symbols: &mut HashMap<String, CalculatorValue>,
+    book: &Workbook,
+    trace: Option<&mut Trace>,
+) -> Result<CalculatorValue, CalculatorError> {
+    let rec_count = symbols.get_mut("%recursion");
+    if let Some(CalculatorValue::Number(n)) = rec_count {
+        *n += 1.0;
+        if *n > RECURSION_LIMIT {
+            return Err(CalculatorError::RecursionLimit);
+        }
+    }
+    tree.visit(symbols, book, trace)
 }
 
+///Evaluates equation text that is not in a cell, such as one given on the command line
 pub fn calculate(
     equation: &str,
     symbols: &mut HashMap<String, CalculatorValue>,
-    table: &Table,
+    book: &Workbook,
 ) -> Result<CalculatorValue, CalculatorError> {
-    let toks = get_tokens(equation);
-    return calcualte_from_tokens(toks, symbols, table);
+    let tree = build_tree(equation);
+    return evaluate(&tree, symbols, book, None);
+}
+
+pub fn get_tokens(equation: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(equation);
+    let toks = lexer.tokenize();
+    return toks;
+}
+
+///Parses an equation, an empty equation becomes 0
+pub fn build_tree(equation: &str) -> Rc<Node> {
+    let tokens = get_tokens(equation);
+    if tokens.len() == 0 {
+        return Rc::new(Node::Number(0.0));
+    }
+    let mut parser = Parser::new(tokens);
+    return Rc::new(parser.build_tree());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheet_tokenizer;
+
+    fn book(text: &str) -> Workbook {
+        let (book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        assert!(errors.is_empty(), "{:?}", errors);
+        return book;
+    }
+
+    fn eval(book: &Workbook, equation: &str) -> String {
+        let mut symbols = cell_symbols(&Position { row: 0, col: 0 });
+        return match calculate(equation, &mut symbols, book) {
+            Ok(v) => book.table().format_result(&v),
+            Err(e) => table::error_text(&e).to_string(),
+        };
+    }
+
+    #[test]
+    fn sum_and_mean_convert_units() {
+        let book = book("#!tsheet 2\n[1,]\n");
+        assert_eq!(eval(&book, "sum(1 m, 500 mm)"), eval(&book, "1 m + 500 mm"));
+        assert_eq!(eval(&book, "sum(1 m, 500 mm)"), "1.5 m");
+        assert_eq!(eval(&book, "mean(1 m, 500 mm)"), "0.75 m");
+        assert_eq!(eval(&book, "sum(1 m, 2 s)"), "Err#5: Mismatched units");
+        assert_eq!(eval(&book, "sum(1 m, 2)"), "Err#5: Mismatched units");
+    }
+
+    #[test]
+    fn randarray_rejects_bad_counts() {
+        let book = book("#!tsheet 2\n%seed 7\n[1,]\n");
+        assert_eq!(eval(&book, "sum(randarray(3, 1, 1))"), "3");
+        assert_eq!(eval(&book, "sum(randarray(0))"), "0");
+        for count in ["1000000000000", "-1", "0/0", "1/0"] {
+            let equation = format!("randarray({})", count);
+            assert_eq!(eval(&book, &equation), "Err#8: Invalid argument", "{}", count);
+        }
+    }
+
+    #[test]
+    fn sum_and_mean_of_ranges() {
+        let book = book("#!tsheet 2\n[1,2,\"x\",(3),]\n");
+        assert_eq!(eval(&book, "sum($A1:$D1)"), "6");
+        assert_eq!(eval(&book, "mean($A1:$D1)"), "2");
+    }
+
+    #[test]
+    fn circular_references_are_errors() {
+        let book = book(
+            "#!tsheet 2\n[($a1),($a1 + 1),(sum($b1, 2))]\n[1,($a2 * 2),($b2 + $a2)]\n",
+        );
+        for cell in ["$A1", "$B1", "$C1"] {
+            assert_eq!(eval(&book, cell), "Err#9: Circular reference", "{}", cell);
+        }
+        assert_eq!(eval(&book, "$C2"), "3");
+        assert_eq!(eval(&book, "$B2 + $B2"), "4");
+    }
+
+    #[test]
+    fn arithmetic_with_units() {
+        let book = book("#!tsheet 2\n[(2 m),]\n");
+        assert_eq!(eval(&book, "12 mm + 1 in"), "37.4 mm");
+        assert_eq!(eval(&book, "10 m / 2 s"), "5 m/s");
+        assert_eq!(eval(&book, "$A1 * 3"), "6 m");
+        assert_eq!(eval(&book, "-$A1"), "-2 m");
+        assert_eq!(eval(&book, "1 m / 1 mm"), "1000");
+        assert_eq!(eval(&book, "convert(1 h, \"min\")"), "60 min");
+        assert_eq!(eval(&book, "1 m + 1 s"), "Err#5: Mismatched units");
+        assert_eq!(eval(&book, "convert(1 m, \"s\")"), "Err#5: Mismatched units");
+    }
 }
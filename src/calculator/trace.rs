@@ -0,0 +1,48 @@
+use super::{CalculatorError, CalculatorValue};
+use crate::table::format_value;
+
+///Collects each step of an evaluation as indented lines
+pub struct Trace {
+    lines: Vec<String>,
+    depth: usize,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace {
+            lines: vec![],
+            depth: 0,
+        }
+    }
+
+    ///Reserves a line for a node whose value is not known yet, lines recorded before the node is
+    ///finished are nested under it
+    pub fn begin(&mut self) -> usize {
+        self.lines.push(String::new());
+        self.depth += 1;
+        return self.lines.len() - 1;
+    }
+
+    pub fn finish(
+        &mut self,
+        line: usize,
+        text: &str,
+        result: &Result<CalculatorValue, CalculatorError>,
+    ) {
+        self.depth -= 1;
+        let value = match result {
+            Ok(v) => format_value(v),
+            Err(e) => format!("error: {:?}", e),
+        };
+        self.lines[line] = format!("{}{} = {}", "  ".repeat(self.depth), text, value);
+    }
+
+    pub fn record(&mut self, text: &str) {
+        self.lines
+            .push(format!("{}{}", "  ".repeat(self.depth), text));
+    }
+
+    pub fn get_lines(self) -> Vec<String> {
+        return self.lines;
+    }
+}
@@ -0,0 +1,202 @@
+///Exponents of length, mass, time, current, temperature and amount
+pub type Dimensions = [i32; 6];
+
+///name, size in SI units, dimensions
+static UNITS: &[(&str, f64, Dimensions)] = &[
+    ("m", 1.0, [1, 0, 0, 0, 0, 0]),
+    ("mm", 0.001, [1, 0, 0, 0, 0, 0]),
+    ("cm", 0.01, [1, 0, 0, 0, 0, 0]),
+    ("km", 1000.0, [1, 0, 0, 0, 0, 0]),
+    ("in", 0.0254, [1, 0, 0, 0, 0, 0]),
+    ("ft", 0.3048, [1, 0, 0, 0, 0, 0]),
+    ("yd", 0.9144, [1, 0, 0, 0, 0, 0]),
+    ("mi", 1609.344, [1, 0, 0, 0, 0, 0]),
+    ("g", 0.001, [0, 1, 0, 0, 0, 0]),
+    ("mg", 0.000001, [0, 1, 0, 0, 0, 0]),
+    ("kg", 1.0, [0, 1, 0, 0, 0, 0]),
+    ("t", 1000.0, [0, 1, 0, 0, 0, 0]),
+    ("oz", 0.028349523125, [0, 1, 0, 0, 0, 0]),
+    ("lb", 0.45359237, [0, 1, 0, 0, 0, 0]),
+    ("s", 1.0, [0, 0, 1, 0, 0, 0]),
+    ("ms", 0.001, [0, 0, 1, 0, 0, 0]),
+    ("min", 60.0, [0, 0, 1, 0, 0, 0]),
+    ("h", 3600.0, [0, 0, 1, 0, 0, 0]),
+    ("day", 86400.0, [0, 0, 1, 0, 0, 0]),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0]),
+    ("mA", 0.001, [0, 0, 0, 1, 0, 0]),
+    ("K", 1.0, [0, 0, 0, 0, 1, 0]),
+    ("mol", 1.0, [0, 0, 0, 0, 0, 1]),
+    ("l", 0.001, [3, 0, 0, 0, 0, 0]),
+    ("ml", 0.000001, [3, 0, 0, 0, 0, 0]),
+    ("N", 1.0, [1, 1, -2, 0, 0, 0]),
+    ("J", 1.0, [2, 1, -2, 0, 0, 0]),
+    ("W", 1.0, [2, 1, -3, 0, 0, 0]),
+    ("V", 1.0, [2, 1, -3, -1, 0, 0]),
+];
+
+fn find_unit(name: &str) -> Option<&'static (&'static str, f64, Dimensions)> {
+    return UNITS.iter().find(|u| u.0 == name);
+}
+
+pub fn is_unit(name: &str) -> bool {
+    return find_unit(name).is_some();
+}
+
+///A unit made of named units raised to a power, eg: kg*m/s^2
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    parts: Vec<(String, i32)>,
+}
+
+impl Unit {
+    ///The unit of a plain number
+    pub fn none() -> Unit {
+        return Unit { parts: vec![] };
+    }
+
+    ///Parses text such as `mm`, `m/s` or `kg*m/s^2`
+    pub fn parse(text: &str) -> Option<Unit> {
+        let mut unit = Unit { parts: vec![] };
+        let mut sign = 1;
+        let mut name = String::new();
+        for ch in text.chars().chain(std::iter::once('*')) {
+            match ch {
+                '*' | '/' => {
+                    let (base, power) = match name.split_once('^') {
+                        Some((b, p)) => (b.trim(), p.trim().parse::<i32>().ok()?),
+                        None => (name.trim(), 1),
+                    };
+                    if base != "1" {
+                        if !is_unit(base) {
+                            return None;
+                        }
+                        unit.add_part(base, power * sign);
+                    }
+                    name = String::new();
+                    sign = if ch == '/' { -1 } else { 1 };
+                }
+                _ => name += &String::from(ch),
+            }
+        }
+        return Some(unit);
+    }
+
+    fn add_part(&mut self, name: &str, power: i32) {
+        match self.parts.iter_mut().find(|p| p.0 == name) {
+            Some(part) => part.1 += power,
+            None => self.parts.push((name.to_string(), power)),
+        }
+        self.parts.retain(|p| p.1 != 0);
+    }
+
+    ///How many SI units one of this unit is
+    pub fn factor(&self) -> f64 {
+        let mut factor = 1.0;
+        for (name, power) in &self.parts {
+            factor *= find_unit(name).map_or(1.0, |u| u.1).powi(*power);
+        }
+        return factor;
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        let mut dims: Dimensions = [0; 6];
+        for (name, power) in &self.parts {
+            if let Some(u) = find_unit(name) {
+                for (dim, unit_dim) in dims.iter_mut().zip(u.2) {
+                    *dim += unit_dim * power;
+                }
+            }
+        }
+        return dims;
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        return self.dimensions() == [0; 6];
+    }
+
+    pub fn mul(&self, other: &Unit) -> Unit {
+        let mut unit = self.clone();
+        for (name, power) in &other.parts {
+            unit.add_part(name, *power);
+        }
+        return unit;
+    }
+
+    pub fn div(&self, other: &Unit) -> Unit {
+        let mut unit = self.clone();
+        for (name, power) in &other.parts {
+            unit.add_part(name, -power);
+        }
+        return unit;
+    }
+
+    ///Converts n in this unit to other, None if the dimensions are different
+    pub fn convert(&self, n: f64, other: &Unit) -> Option<f64> {
+        if self.dimensions() != other.dimensions() {
+            return None;
+        }
+        return Some(n * self.factor() / other.factor());
+    }
+
+    pub fn name(&self) -> String {
+        let mut text = String::new();
+        let format_part = |name: &str, power: i32| {
+            if power == 1 {
+                name.to_string()
+            } else {
+                format!("{}^{}", name, power)
+            }
+        };
+        for (name, power) in self.parts.iter().filter(|p| p.1 > 0) {
+            if text.len() > 0 {
+                text += "*";
+            }
+            text += &format_part(name, *power);
+        }
+        if text.len() == 0 {
+            text += "1";
+        }
+        for (name, power) in self.parts.iter().filter(|p| p.1 < 0) {
+            text += &format!("/{}", format_part(name, -power));
+        }
+        return text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(text: &str) -> Unit {
+        return Unit::parse(text).unwrap();
+    }
+
+    #[test]
+    fn parses_and_names_units() {
+        assert_eq!(unit("kg*m/s^2").name(), "kg*m/s^2");
+        assert_eq!(unit("1/s").name(), "1/s");
+        assert_eq!(unit("m/m").name(), "1");
+        assert_eq!(unit(" m / s ").name(), "m/s");
+        assert_eq!(unit("kg*m/s^2").dimensions(), unit("N").dimensions());
+        assert_eq!(Unit::parse("furlong"), None);
+        assert_eq!(Unit::parse("m^x"), None);
+    }
+
+    #[test]
+    fn converts_between_units_of_the_same_dimensions() {
+        assert_eq!(unit("in").convert(1.0, &unit("mm")), Some(25.4));
+        assert_eq!(unit("km/h").convert(36.0, &unit("m/s")), Some(10.0));
+        assert_eq!(unit("l").convert(1.0, &unit("cm^3")).map(f64::round), Some(1000.0));
+        assert_eq!(unit("m").convert(1.0, &unit("s")), None);
+    }
+
+    #[test]
+    fn combines_units() {
+        let speed = unit("m").div(&unit("s"));
+        assert_eq!(speed.name(), "m/s");
+        assert_eq!(speed.mul(&unit("s")).name(), "m");
+        assert!(unit("mm").div(&unit("m")).is_dimensionless());
+        assert_eq!(unit("mm").div(&unit("m")).factor(), 0.001);
+        assert!(Unit::none().is_dimensionless());
+    }
+}
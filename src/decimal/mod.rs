@@ -0,0 +1,354 @@
+use std::fmt;
+
+///Digits kept after the point when dividing without a fixed number of places
+pub static EXACT_DIVISION_PLACES: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    HalfEven,
+    HalfUp,
+    HalfDown,
+    Up,
+    Down,
+    Ceiling,
+    Floor,
+}
+
+impl RoundingMode {
+    pub fn from_name(name: &str) -> Option<RoundingMode> {
+        match name {
+            "half-even" => Some(RoundingMode::HalfEven),
+            "half-up" => Some(RoundingMode::HalfUp),
+            "half-down" => Some(RoundingMode::HalfDown),
+            "up" => Some(RoundingMode::Up),
+            "down" => Some(RoundingMode::Down),
+            "ceiling" => Some(RoundingMode::Ceiling),
+            "floor" => Some(RoundingMode::Floor),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoundingMode::HalfEven => "half-even",
+            RoundingMode::HalfUp => "half-up",
+            RoundingMode::HalfDown => "half-down",
+            RoundingMode::Up => "up",
+            RoundingMode::Down => "down",
+            RoundingMode::Ceiling => "ceiling",
+            RoundingMode::Floor => "floor",
+        }
+    }
+}
+
+///A base 10 number, the value is mantissa / 10^scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+fn pow10(n: u32) -> Option<i128> {
+    return 10i128.checked_pow(n);
+}
+
+///Divides n by d, rounding the result with mode
+fn round_div(mut n: i128, mut d: i128, mode: RoundingMode) -> i128 {
+    if d < 0 {
+        n = -n;
+        d = -d;
+    }
+    let q = n / d;
+    let r = n % d;
+    if r == 0 {
+        return q;
+    }
+    let sign = n.signum();
+    let away = q + sign;
+    //compare the remainder to half of d without overflowing
+    let half = r.abs().cmp(&(d - r.abs()));
+    return match mode {
+        RoundingMode::Down => q,
+        RoundingMode::Up => away,
+        RoundingMode::Ceiling => {
+            if sign > 0 {
+                away
+            } else {
+                q
+            }
+        }
+        RoundingMode::Floor => {
+            if sign < 0 {
+                away
+            } else {
+                q
+            }
+        }
+        RoundingMode::HalfUp => {
+            if half.is_ge() {
+                away
+            } else {
+                q
+            }
+        }
+        RoundingMode::HalfDown => {
+            if half.is_gt() {
+                away
+            } else {
+                q
+            }
+        }
+        RoundingMode::HalfEven => match half {
+            std::cmp::Ordering::Greater => away,
+            std::cmp::Ordering::Less => q,
+            std::cmp::Ordering::Equal => {
+                if q % 2 == 0 {
+                    q
+                } else {
+                    away
+                }
+            }
+        },
+    };
+}
+
+impl Decimal {
+    pub fn zero() -> Decimal {
+        Decimal {
+            mantissa: 0,
+            scale: 0,
+        }
+    }
+
+    ///Parses text such as `-12.50` or `1e-7`
+    pub fn parse(text: &str) -> Option<Decimal> {
+        let text = text.trim();
+        let (negative, text) = match text.chars().next() {
+            Some('-') => (true, &text[1..]),
+            Some('+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (number, exponent) = match text.find(['e', 'E']) {
+            Some(i) => (&text[0..i], text[i + 1..].parse::<i32>().ok()?),
+            None => (text, 0),
+        };
+        let (int_part, frac_part) = match number.find('.') {
+            Some(i) => (&number[0..i], &number[i + 1..]),
+            None => (number, ""),
+        };
+        if int_part.len() + frac_part.len() == 0 {
+            return None;
+        }
+        let mut mantissa: i128 = 0;
+        for ch in int_part.chars().chain(frac_part.chars()) {
+            let digit = ch.to_digit(10)? as i128;
+            mantissa = mantissa.checked_mul(10)?.checked_add(digit)?;
+        }
+        let mut scale = i32::try_from(frac_part.len()).ok()?.checked_sub(exponent)?;
+        if scale < 0 {
+            mantissa = mantissa.checked_mul(pow10(scale.checked_neg()? as u32)?)?;
+            scale = 0;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+        return Some(Decimal {
+            mantissa,
+            scale: scale as u32,
+        });
+    }
+
+    ///Converts using the shortest text that round trips, so 0.1 becomes exactly 0.1
+    pub fn from_f64(n: f64) -> Option<Decimal> {
+        if !n.is_finite() {
+            return None;
+        }
+        return Decimal::parse(&n.to_string());
+    }
+
+    pub fn to_f64(self) -> f64 {
+        return self.to_string().parse().unwrap_or(0.0);
+    }
+
+    pub fn is_zero(&self) -> bool {
+        return self.mantissa == 0;
+    }
+
+    fn with_scale(&self, scale: u32) -> Option<Decimal> {
+        if scale <= self.scale {
+            return Some(*self);
+        }
+        let mantissa = self.mantissa.checked_mul(pow10(scale - self.scale)?)?;
+        return Some(Decimal { mantissa, scale });
+    }
+
+    ///Removes trailing zeros after the point
+    fn normalize(mut self) -> Decimal {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        return self;
+    }
+
+    pub fn round(&self, places: u32, mode: RoundingMode) -> Decimal {
+        if self.scale <= places {
+            return *self;
+        }
+        //a scale this large can only round to 0
+        let divisor = match pow10(self.scale - places) {
+            Some(d) => d,
+            None => return Decimal::zero(),
+        };
+        return Decimal {
+            mantissa: round_div(self.mantissa, divisor, mode),
+            scale: places,
+        };
+    }
+
+    pub fn add(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let a = self.with_scale(scale)?;
+        let b = other.with_scale(scale)?;
+        return Some(Decimal {
+            mantissa: a.mantissa.checked_add(b.mantissa)?,
+            scale,
+        });
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Option<Decimal> {
+        return self.add(&Decimal {
+            mantissa: -other.mantissa,
+            scale: other.scale,
+        });
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Option<Decimal> {
+        let result = Decimal {
+            mantissa: self.mantissa.checked_mul(other.mantissa)?,
+            scale: self.scale.checked_add(other.scale)?,
+        };
+        return Some(result.normalize());
+    }
+
+    ///Divides, keeping places digits after the point. Returns None when dividing by 0 or if the
+    ///result does not fit
+    pub fn div(&self, other: &Decimal, places: u32, mode: RoundingMode) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        //mantissa / 10^scale / (other.mantissa / 10^other.scale) shifted left by places
+        let shift = places as i64 + other.scale as i64 - self.scale as i64;
+        let (numerator, denominator) = if shift >= 0 {
+            (
+                self.mantissa.checked_mul(pow10(shift as u32)?)?,
+                other.mantissa,
+            )
+        } else {
+            (
+                self.mantissa,
+                other.mantissa.checked_mul(pow10((-shift) as u32)?)?,
+            )
+        };
+        let result = Decimal {
+            mantissa: round_div(numerator, denominator, mode),
+            scale: places,
+        };
+        return Some(result.normalize());
+    }
+
+    ///Formats with exactly places digits after the point
+    pub fn format_fixed(&self, places: u32, mode: RoundingMode) -> String {
+        let rounded = self.round(places, mode);
+        return match rounded.with_scale(places) {
+            Some(d) => d.to_string(),
+            None => rounded.to_string(),
+        };
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let (int_part, frac_part) = padded.split_at(padded.len() - scale);
+        return write!(f, "{}{}.{}", sign, int_part, frac_part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(text: &str) -> Decimal {
+        return Decimal::parse(text).unwrap();
+    }
+
+    #[test]
+    fn parses_and_prints() {
+        assert_eq!(d("-12.50").to_string(), "-12.50");
+        assert_eq!(d("1e-7").to_string(), "0.0000001");
+        assert_eq!(d("2.5E3").to_string(), "2500");
+        assert_eq!(d(".5").to_string(), "0.5");
+        assert_eq!(Decimal::from_f64(0.1).unwrap().to_string(), "0.1");
+        for text in ["", "-", "1.2.3", "abc", "1e", "99999999999999999999999999999999999999999"] {
+            assert_eq!(Decimal::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn adds_exactly() {
+        assert_eq!(d("0.1").add(&d("0.2")).unwrap(), d("0.3"));
+        assert_eq!(d("1.5").sub(&d("2.25")).unwrap().to_string(), "-0.75");
+        assert_eq!(d("1.5").mul(&d("0.2")).unwrap().to_string(), "0.3");
+    }
+
+    #[test]
+    fn divides_to_places() {
+        let mode = RoundingMode::HalfEven;
+        assert_eq!(d("1").div(&d("3"), 4, mode).unwrap().to_string(), "0.3333");
+        assert_eq!(d("2").div(&d("3"), 4, mode).unwrap().to_string(), "0.6667");
+        assert_eq!(d("-2").div(&d("3"), 4, mode).unwrap().to_string(), "-0.6667");
+        assert_eq!(d("1").div(&d("8"), 20, mode).unwrap().to_string(), "0.125");
+        assert_eq!(d("0.01").div(&d("0.1"), 0, mode).unwrap().to_string(), "0");
+        assert_eq!(d("12.5").div(&d("0.5"), 2, mode).unwrap().to_string(), "25");
+        assert_eq!(d("1").div(&d("0"), 2, mode), None);
+        assert_eq!(d("1").div(&d("3"), 40, mode), None);
+    }
+
+    #[test]
+    fn rounds_with_each_mode() {
+        let cases = [
+            (RoundingMode::HalfEven, ["2", "2", "-2", "0", "0"]),
+            (RoundingMode::HalfUp, ["3", "2", "-3", "1", "-1"]),
+            (RoundingMode::HalfDown, ["2", "2", "-2", "0", "0"]),
+            (RoundingMode::Up, ["3", "3", "-3", "1", "-1"]),
+            (RoundingMode::Down, ["2", "2", "-2", "0", "0"]),
+            (RoundingMode::Ceiling, ["3", "3", "-2", "1", "0"]),
+            (RoundingMode::Floor, ["2", "2", "-3", "0", "-1"]),
+        ];
+        for (mode, expected) in cases {
+            let rounded: Vec<String> = ["2.5", "2.1", "-2.5", "0.5", "-0.5"]
+                .iter()
+                .map(|n| d(n).round(0, mode).to_string())
+                .collect();
+            assert_eq!(rounded, expected, "{}", mode.name());
+        }
+        assert_eq!(d("1.005").round(2, RoundingMode::HalfUp).to_string(), "1.01");
+        assert_eq!(d("1.5").round(3, RoundingMode::HalfUp).to_string(), "1.5");
+        assert_eq!(d("1.2").format_fixed(3, RoundingMode::HalfUp), "1.200");
+    }
+
+    #[test]
+    fn reads_rounding_mode_names() {
+        for name in ["half-even", "half-up", "half-down", "up", "down", "ceiling", "floor"] {
+            assert_eq!(RoundingMode::from_name(name).map(|m| m.name()), Some(name));
+        }
+        assert_eq!(RoundingMode::from_name("nearest"), None);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use encoding_rs::Encoding;
+
+use super::{cell_text, data_from_field, Contents};
+use crate::{
+    locale::Locale,
+    table::{Data, Position, Table},
+    workbook::Workbook,
+};
+
+///How a csv file is read, set with `-d`, `-q`, `--header` and `-e`
+#[derive(Debug, Clone, Copy)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub quote: u8,
+    ///The first row is column names, it is kept as text and frozen
+    pub header: bool,
+    pub encoding: &'static Encoding,
+}
+
+///Parses a delimiter or quote character, `tab` and `\t` are a tab
+fn parse_char(text: &str) -> Option<u8> {
+    return match text {
+        "tab" | "\\t" => Some(b'\t'),
+        _ if text.len() == 1 && text.is_ascii() => Some(text.as_bytes()[0]),
+        _ => None,
+    };
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        return CsvOptions {
+            delimiter: b',',
+            quote: b'"',
+            header: false,
+            encoding: encoding_rs::UTF_8,
+        };
+    }
+}
+
+impl CsvOptions {
+    ///Reads the options from the command line, the delimiter is the locale's csv separator when
+    ///not given
+    pub fn from_args(opts: &HashMap<String, String>, locale: &Locale) -> Result<CsvOptions, String> {
+        let delimiter = match opts.get("-d") {
+            None => locale.csv_separator() as u8,
+            Some(d) => parse_char(d).ok_or(format!("Invalid delimiter: {}", d))?,
+        };
+        let quote = match opts.get("-q") {
+            None => b'"',
+            Some(q) => parse_char(q).ok_or(format!("Invalid quote character: {}", q))?,
+        };
+        let header = match opts.get("--header").map(|h| h.as_str()) {
+            None | Some("no") => false,
+            Some("yes") => true,
+            Some(h) => return Err(format!("--header must be yes or no, not {}", h)),
+        };
+        let encoding = match opts.get("-e") {
+            None => encoding_rs::UTF_8,
+            Some(e) => super::parse_encoding(e).ok_or(format!("Unknown encoding: {}", e))?,
+        };
+        return Ok(CsvOptions {
+            delimiter,
+            quote,
+            header,
+            encoding,
+        });
+    }
+}
+
+///Reads a csv file as described by RFC 4180, fields may be quoted to hold the delimiter, quotes
+///and line breaks, and rows may end with CRLF. Rows that cannot be read are returned as problems
+pub fn read(bytes: &[u8], options: &CsvOptions, locale: Locale) -> (Table, Vec<String>) {
+    let text = super::decode(bytes, options.encoding);
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut rows = vec![];
+    let mut errors: Vec<String> = vec![];
+    for (i, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                errors.push(e.to_string());
+                continue;
+            }
+        };
+        let row: Vec<Data> = if options.header && i == 0 {
+            record.iter().map(|f| Data::String(f.to_string())).collect()
+        } else {
+            record.iter().map(|f| data_from_field(f, &locale)).collect()
+        };
+        rows.push(row);
+    }
+    let mut table = Table::from_rows(rows, locale);
+    if options.header {
+        table.get_settings_mut().frozen_rows = 1;
+    }
+    return (table, errors);
+}
+
+///Writes the sheet being edited as csv in the options' encoding, fields are quoted when they hold
+///the delimiter, the quote character or a line break
+pub fn write(book: &Workbook, options: &CsvOptions, contents: Contents) -> Vec<u8> {
+    let table = book.table();
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .from_writer(vec![]);
+    for (row_no, row) in table.get_rows().iter().enumerate() {
+        let fields = row.iter().enumerate().map(|(col_no, item)| {
+            let pos = Position {
+                row: row_no,
+                col: col_no,
+            };
+            cell_text(book, &pos, item, contents)
+        });
+        //writing to a vec cannot fail
+        writer.write_record(fields).unwrap();
+    }
+    let text = String::from_utf8(writer.into_inner().unwrap_or_default()).unwrap_or_default();
+    let (bytes, _, _) = options.encoding.encode(&text);
+    return bytes.into_owned();
+}
@@ -0,0 +1,215 @@
+use super::{cell_text, data_from_field, Contents};
+use crate::{
+    locale::Locale,
+    table::{Data, Position, Table},
+    workbook::Workbook,
+};
+
+///Tabs are expanded to the next multiple of this many characters
+const TAB_WIDTH: usize = 8;
+
+///Parses widths such as `8,6,12`
+pub fn parse_widths(text: &str) -> Option<Vec<usize>> {
+    let widths: Option<Vec<usize>> = text
+        .split(',')
+        .map(|w| w.trim().parse().ok().filter(|w| *w > 0))
+        .collect();
+    return widths.filter(|w| w.len() > 0);
+}
+
+fn expand_tabs(line: &str) -> Vec<char> {
+    let mut chars: Vec<char> = vec![];
+    for ch in line.chars() {
+        if ch == '\t' {
+            let spaces = TAB_WIDTH - chars.len() % TAB_WIDTH;
+            chars.extend(std::iter::repeat_n(' ', spaces));
+        } else {
+            chars.push(ch);
+        }
+    }
+    return chars;
+}
+
+fn is_blank(line: &[char], col: usize) -> bool {
+    return line.get(col).is_none_or(|c| *c == ' ');
+}
+
+///Guesses where each column starts from the character positions that are blank on every line.
+///A column starts after a blank position, except after a single blank inside the first line when
+///no other line has anything under the text after it, as in a header like `Mounted on`
+fn guess_starts(lines: &[Vec<char>]) -> Vec<usize> {
+    let width = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+    let blank: Vec<bool> = (0..width)
+        .map(|col| lines.iter().all(|l| is_blank(l, col)))
+        .collect();
+    let mut starts: Vec<usize> = (0..width)
+        .filter(|col| !blank[*col] && (*col == 0 || blank[col - 1]))
+        .collect();
+    let mut i = 1;
+    while i < starts.len() && lines.len() > 1 {
+        let start = starts[i];
+        let end = starts.get(i + 1).copied().unwrap_or(width);
+        let single_gap = start >= 2 && !blank[start - 2];
+        let only_header = lines[1..]
+            .iter()
+            .all(|l| (start..end).all(|col| is_blank(l, col)));
+        if single_gap && only_header {
+            starts.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    //text before the first column belongs to it
+    if let Some(first) = starts.first_mut() {
+        *first = 0;
+    }
+    return starts;
+}
+
+///Reads text laid out in columns of fixed width, such as reports or the output of `ps` and `df`.
+///Columns are found from the blank positions shared by every line unless their widths are given,
+///text past the last given width is kept in the last column. A first line without numbers is a
+///header, which is kept as text and frozen. Each column is as wide on screen as in the text
+pub fn read(text: &str, widths: Option<&Vec<usize>>, locale: Locale) -> (Table, Vec<String>) {
+    let lines: Vec<Vec<char>> = text
+        .lines()
+        .filter(|l| l.trim().len() > 0)
+        .map(expand_tabs)
+        .collect();
+    let mut errors: Vec<String> = vec![];
+    if lines.len() == 0 {
+        errors.push("No lines to read".to_string());
+    }
+    let starts: Vec<usize> = match widths {
+        Some(widths) => std::iter::once(0)
+            .chain(widths.iter().scan(0, |end, w| {
+                *end += w;
+                Some(*end)
+            }))
+            .take(widths.len())
+            .collect(),
+        None => guess_starts(&lines),
+    };
+    let longest = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+    let mut rows: Vec<Vec<String>> = vec![];
+    for line in &lines {
+        let fields = starts.iter().enumerate().map(|(i, start)| {
+            let end = starts.get(i + 1).copied().unwrap_or(line.len()).min(line.len());
+            let field: String = line[(*start).min(end)..end].iter().collect();
+            field.trim().to_string()
+        });
+        rows.push(fields.collect());
+    }
+    let header = rows.len() > 1
+        && rows[0]
+            .iter()
+            .all(|field| locale.parse_number(field).is_none());
+    let data: Vec<Vec<Data>> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            row.iter()
+                .map(|field| match header && i == 0 {
+                    true => Data::String(field.clone()),
+                    false => data_from_field(field, &locale),
+                })
+                .collect()
+        })
+        .collect();
+    let mut table = Table::from_rows(data, locale);
+    for (i, start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(longest.max(start + 1));
+        table.resize_col(i, end - start);
+    }
+    if header {
+        table.get_settings_mut().frozen_rows = 1;
+    }
+    return (table, errors);
+}
+
+///Writes each cell padded to the width of its column, or of its text if that is wider
+pub fn write(book: &Workbook, contents: Contents) -> String {
+    let table = book.table();
+    let rows: Vec<Vec<String>> = table
+        .get_rows()
+        .iter()
+        .enumerate()
+        .map(|(row_no, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(col_no, item)| {
+                    let pos = Position {
+                        row: row_no,
+                        col: col_no,
+                    };
+                    cell_text(book, &pos, item, contents).replace('\n', " ")
+                })
+                .collect()
+        })
+        .collect();
+    let cols = rows.first().map_or(0, |r| r.len());
+    //a space is left between columns so they can be found again
+    let widths: Vec<usize> = (0..cols)
+        .map(|col| {
+            let widest = rows.iter().map(|r| r[col].chars().count() + 1).max();
+            table.get_col_width(col).unwrap_or(0).max(widest.unwrap_or(0))
+        })
+        .collect();
+    let mut text = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(&widths) {
+            line += &format!("{:<width$}", cell, width = width);
+        }
+        text += line.trim_end();
+        text += "\n";
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::cell_texts;
+
+    #[test]
+    fn guesses_columns_of_df_output() {
+        let text = "Filesystem     Size  Mounted on\n\
+                    /dev/sda1      20G   /\n\
+                    tmpfs\t       512M  /run/user\n";
+        let (table, errors) = read(text, None, Locale::canonical());
+        assert!(errors.is_empty());
+        assert_eq!(
+            cell_texts(&table),
+            vec![
+                vec!["Filesystem", "Size", "Mounted on"],
+                vec!["/dev/sda1", "20G", "/"],
+                vec!["tmpfs", "512M", "/run/user"],
+            ]
+        );
+        assert_eq!(table.get_settings().frozen_rows, 1);
+        assert_eq!(table.get_col_width(0), Some(15));
+    }
+
+    #[test]
+    fn reads_given_widths() {
+        let widths = parse_widths("3, 2").unwrap();
+        let (table, _) = read("abc12rest\nde 3\n", Some(&widths), Locale::canonical());
+        assert_eq!(cell_texts(&table), vec![vec!["abc", "12rest"], vec!["de", "3"]]);
+        assert_eq!(parse_widths("3,0"), None);
+        assert_eq!(parse_widths("a"), None);
+    }
+
+    #[test]
+    fn writes_columns_that_read_back() {
+        let rows = vec![
+            vec![Data::String("name".to_string()), Data::String("size".to_string())],
+            vec![Data::String("a-long-name".to_string()), Data::Number("12".to_string())],
+        ];
+        let book = Workbook::new(Table::from_rows(rows, Locale::canonical()));
+        let text = write(&book, Contents::Values);
+        assert_eq!(text, "name        size\na-long-name 12\n");
+        let (table, _) = read(&text, None, Locale::canonical());
+        assert_eq!(cell_texts(&table), cell_texts(book.table()));
+    }
+}
@@ -0,0 +1,90 @@
+use super::{cell_text, Contents, ValueKind};
+use crate::table::Position;
+use crate::workbook::Workbook;
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            '\n' => escaped += "<br>",
+            _ => escaped += &String::from(ch),
+        }
+    }
+    return escaped;
+}
+
+///Writes a `<table>` with the first row as the header. With classes each cell has a class for
+///the kind of value in it, `number`, `text` or `error`, and cells with equations also have the
+///class `formula`
+pub fn write(book: &Workbook, contents: Contents, classes: bool) -> String {
+    let table = book.table();
+    let mut text = String::from("<table>\n");
+    for (row_no, row) in table.get_rows().iter().enumerate() {
+        if row_no == 0 {
+            text += "  <thead>\n";
+        } else if row_no == 1 {
+            text += "  <tbody>\n";
+        }
+        let tag = if row_no == 0 { "th" } else { "td" };
+        text += "    <tr>";
+        for (col_no, item) in row.iter().enumerate() {
+            let pos = Position {
+                row: row_no,
+                col: col_no,
+            };
+            let value = escape(&cell_text(book, &pos, item, contents));
+            if !classes {
+                text += &format!("<{}>{}</{}>", tag, value, tag);
+                continue;
+            }
+            let mut class = ValueKind::of(book, &pos, item).name().to_string();
+            if let crate::table::Data::Equation(..) = item {
+                class += " formula";
+            }
+            text += &format!("<{} class=\"{}\">{}</{}>", tag, class, value, tag);
+        }
+        text += "</tr>\n";
+        if row_no == 0 {
+            text += "  </thead>\n";
+        }
+    }
+    if table.get_rows().len() > 1 {
+        text += "  </tbody>\n";
+    }
+    return text + "</table>\n";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheet_tokenizer;
+
+    fn book(text: &str) -> Workbook {
+        let (book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        assert!(errors.is_empty(), "{:?}", errors);
+        return book;
+    }
+
+    #[test]
+    fn writes_a_header_and_escaped_cells() {
+        let book = book("#!tsheet 2\n[\"a < b\",\"it's\"]\n[1,(2 * $A2)]\n");
+        assert_eq!(
+            write(&book, Contents::Values, false),
+            "<table>\n  <thead>\n    <tr><th>a &lt; b</th><th>it&#39;s</th></tr>\n  </thead>\n  \
+             <tbody>\n    <tr><td>1</td><td>2</td></tr>\n  </tbody>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn writes_classes_and_formulas() {
+        let book = book("#!tsheet 2\n[\"x\",]\n[(1 m + 2 s),(3 * 2)]\n");
+        let text = write(&book, Contents::Formulas, true);
+        assert!(text.contains("<td class=\"error formula\">=1 m + 2 s</td>"), "{}", text);
+        assert!(text.contains("<td class=\"number formula\">=3 * 2</td>"), "{}", text);
+    }
+}
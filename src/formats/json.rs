@@ -0,0 +1,168 @@
+use serde_json::{Map, Value};
+
+use super::{cell_text, Contents};
+use crate::{
+    calculator::CalculatorValue,
+    locale::Locale,
+    table::{self, Data, Position, Table},
+    workbook::Workbook,
+};
+
+///Adds the fields of value to fields, nested objects and arrays are flattened into names joined
+///by `.`, eg: `{"a": {"b": 1}, "c": [2]}` has the fields `a.b` and `c.0`
+fn flatten(prefix: &str, value: Value, fields: &mut Vec<(String, Value)>) {
+    let join = |key: &str| {
+        if prefix.len() == 0 {
+            return key.to_string();
+        }
+        return format!("{}.{}", prefix, key);
+    };
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                flatten(&join(&key), v, fields);
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.into_iter().enumerate() {
+                flatten(&join(&i.to_string()), v, fields);
+            }
+        }
+        v => fields.push((prefix.to_string(), v)),
+    }
+}
+
+fn data_from_value(value: Value) -> Data {
+    match value {
+        Value::Number(n) => Data::Number(n.to_string()),
+        Value::String(s) => Data::String(s),
+        Value::Bool(b) => Data::String(b.to_string()),
+        _ => Data::String(String::new()),
+    }
+}
+
+///Reads an array of objects, or JSON Lines when `lines` is set. The names of the objects' fields
+///become a frozen header row, in the order they are first seen
+pub fn read(bytes: &[u8], lines: bool, locale: Locale) -> (Table, Vec<String>) {
+    let text = String::from_utf8_lossy(bytes);
+    let mut errors: Vec<String> = vec![];
+    let mut objects: Vec<(usize, Value)> = vec![];
+    if lines {
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().len() == 0 {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(v) => objects.push((i + 1, v)),
+                Err(e) => errors.push(format!("line {}: {}", i + 1, e)),
+            }
+        }
+    } else {
+        match serde_json::from_str(&text) {
+            Ok(Value::Array(items)) => {
+                objects = items.into_iter().enumerate().map(|(i, v)| (i + 1, v)).collect()
+            }
+            Ok(_) => errors.push("Expected an array of objects".to_string()),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    let mut header: Vec<String> = vec![];
+    let mut rows: Vec<Vec<Data>> = vec![];
+    for (n, object) in objects {
+        if !object.is_object() {
+            let item = if lines { "line" } else { "item" };
+            errors.push(format!("{} {}: Expected an object", item, n));
+            continue;
+        }
+        let mut fields: Vec<(String, Value)> = vec![];
+        flatten("", object, &mut fields);
+        let mut row: Vec<Data> = vec![Data::String(String::new()); header.len()];
+        for (name, value) in fields {
+            let col = match header.iter().position(|h| *h == name) {
+                Some(col) => col,
+                None => {
+                    header.push(name);
+                    row.push(Data::String(String::new()));
+                    header.len() - 1
+                }
+            };
+            row[col] = data_from_value(value);
+        }
+        rows.push(row);
+    }
+    rows.insert(0, header.into_iter().map(Data::String).collect());
+    let mut table = Table::from_rows(rows, locale);
+    table.get_settings_mut().frozen_rows = 1;
+    return (table, errors);
+}
+
+///Whole numbers are written without a point, numbers JSON cannot hold such as NaN are null
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        return Value::from(n as i64);
+    }
+    return serde_json::Number::from_f64(n).map_or(Value::Null, Value::Number);
+}
+
+fn value_from_data(book: &Workbook, pos: &Position, data: &Data, contents: Contents) -> Value {
+    match data {
+        Data::Number(n) => return number(n.parse().unwrap_or(0.0)),
+        Data::String(s) => return Value::String(s.clone()),
+        Data::Equation(..) if contents == Contents::Formulas => {
+            return Value::String(cell_text(book, pos, data, contents))
+        }
+        Data::Equation(..) => match data.evaluate(book, pos) {
+            Some(Ok(CalculatorValue::Number(n))) => return number(n),
+            Some(Ok(CalculatorValue::Decimal(d))) => return number(d.to_f64()),
+            _ => return Value::String(data.value_text(book, pos)),
+        },
+    }
+}
+
+///The field names given by the header row, columns without a name are named by their letter and
+///repeated names get a suffix, as in `name_2`, so that no column is lost
+fn field_names(book: &Workbook, header: &[Data]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for (col, item) in header.iter().enumerate() {
+        let mut name = item.value_text(book, &Position { row: 0, col });
+        if name.len() == 0 {
+            name = table::base_10_to_col_num(col + 1);
+        }
+        let mut unique = name.clone();
+        let mut n = 2;
+        while names.contains(&unique) {
+            unique = format!("{}_{}", name, n);
+            n += 1;
+        }
+        names.push(unique);
+    }
+    return names;
+}
+
+///Writes the rows after the first as an array of objects, or as JSON Lines when `lines` is set.
+///The first row names the fields
+pub fn write(book: &Workbook, lines: bool, contents: Contents) -> String {
+    let rows = book.table().get_rows();
+    let header = rows.first().map_or(vec![], |row| field_names(book, row));
+    let mut objects: Vec<Value> = vec![];
+    for (row_no, row) in rows.iter().enumerate().skip(1) {
+        let mut object = Map::new();
+        for (col_no, item) in row.iter().enumerate() {
+            let pos = Position {
+                row: row_no,
+                col: col_no,
+            };
+            object.insert(
+                header[col_no].clone(),
+                value_from_data(book, &pos, item, contents),
+            );
+        }
+        objects.push(Value::Object(object));
+    }
+    if lines {
+        let lines: Vec<String> = objects.iter().map(|o| o.to_string()).collect();
+        return lines.join("\n") + "\n";
+    }
+    return serde_json::to_string_pretty(&Value::Array(objects)).unwrap_or_default() + "\n";
+}
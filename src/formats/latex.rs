@@ -0,0 +1,78 @@
+use super::{cell_text, Contents, ValueKind};
+use crate::table::Position;
+use crate::workbook::Workbook;
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        match ch {
+            '\\' => escaped += "\\textbackslash{}",
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => escaped += &format!("\\{}", ch),
+            '~' => escaped += "\\textasciitilde{}",
+            '^' => escaped += "\\textasciicircum{}",
+            '\n' => escaped += " ",
+            _ => escaped += &String::from(ch),
+        }
+    }
+    return escaped;
+}
+
+///Writes a tabular using the booktabs package with the first row as the header, columns of
+///numbers are aligned to the right
+pub fn write(book: &Workbook, contents: Contents) -> String {
+    let rows = book.table().get_rows();
+    let cols = rows.first().map_or(0, |r| r.len());
+    let mut spec = String::new();
+    for col in 0..cols {
+        let numbers = rows.iter().enumerate().skip(1).all(|(row, r)| {
+            let pos = Position { row, col };
+            ValueKind::of(book, &pos, &r[col]) == ValueKind::Number
+        });
+        spec += if numbers && rows.len() > 1 { "r" } else { "l" };
+    }
+    let mut text = format!("\\begin{{tabular}}{{{}}}\n\\toprule\n", spec);
+    for (row_no, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(col_no, item)| {
+                let pos = Position {
+                    row: row_no,
+                    col: col_no,
+                };
+                escape(&cell_text(book, &pos, item, contents))
+            })
+            .collect();
+        text += &format!("{} \\\\\n", cells.join(" & "));
+        if row_no == 0 {
+            text += "\\midrule\n";
+        }
+    }
+    return text + "\\bottomrule\n\\end{tabular}\n";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sheet_tokenizer;
+
+    #[test]
+    fn writes_a_booktabs_tabular() {
+        let text = "#!tsheet 2\n[\"Item\",\"Cost\"]\n[\"50% off_\",(10 * 2)]\n[\"{x}\",3]\n";
+        let (book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        assert!(errors.is_empty());
+        assert_eq!(
+            write(&book, Contents::Values),
+            "\\begin{tabular}{lr}\n\\toprule\nItem & Cost \\\\\n\\midrule\n\
+             50\\% off\\_ & 20 \\\\\n\\{x\\} & 3 \\\\\n\\bottomrule\n\\end{tabular}\n"
+        );
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(
+            escape("a\\b~c^d#"),
+            "a\\textbackslash{}b\\textasciitilde{}c\\textasciicircum{}d\\#"
+        );
+    }
+}
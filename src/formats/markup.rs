@@ -0,0 +1,230 @@
+use super::{cell_text, data_from_field, Contents};
+use crate::{
+    locale::Locale,
+    table::{Data, Position, Table},
+    workbook::Workbook,
+};
+
+///The text of each cell, with characters that would end a cell escaped by escape
+fn cells(book: &Workbook, contents: Contents, escape: fn(&str) -> String) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = vec![];
+    for (row_no, row) in book.table().get_rows().iter().enumerate() {
+        rows.push(
+            row.iter()
+                .enumerate()
+                .map(|(col_no, item)| {
+                    let pos = Position {
+                        row: row_no,
+                        col: col_no,
+                    };
+                    //line breaks cannot be written inside a cell
+                    escape(&cell_text(book, &pos, item, contents).replace('\n', " "))
+                })
+                .collect(),
+        );
+    }
+    return rows;
+}
+
+///How wide each column is written, the column's width in the sheet unless a cell is wider
+fn widths(table: &Table, rows: &[Vec<String>]) -> Vec<usize> {
+    let cols = rows.first().map_or(0, |r| r.len());
+    return (0..cols)
+        .map(|col| {
+            let widest = rows.iter().map(|r| r[col].chars().count()).max();
+            return table.get_col_width(col).unwrap_or(0).max(widest.unwrap_or(0));
+        })
+        .collect();
+}
+
+///Writes a row as `| a | b |`
+fn write_row(row: &[String], widths: &[usize]) -> String {
+    let mut text = String::from("|");
+    for (cell, width) in row.iter().zip(widths) {
+        text += &format!(" {:<width$} |", cell, width = width);
+    }
+    return text + "\n";
+}
+
+fn escape_pipe(text: &str) -> String {
+    return text.replace('|', "\\|");
+}
+
+///Writes a GitHub flavoured markdown table, the first row is the header
+pub fn write_markdown(book: &Workbook, contents: Contents) -> String {
+    let rows = cells(book, contents, escape_pipe);
+    //the row under the header must have at least 3 dashes
+    let widths: Vec<usize> = widths(book.table(), &rows).into_iter().map(|w| w.max(3)).collect();
+    let mut text = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        text += &write_row(row, &widths);
+        if i == 0 {
+            let dashes: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            text += &write_row(&dashes, &widths);
+        }
+    }
+    return text;
+}
+
+///Writes an org-mode table, the first row is the header
+pub fn write_org(book: &Workbook, contents: Contents) -> String {
+    //org has no escape for |, \vert is shown as | when exported
+    let rows = cells(book, contents, |t| t.replace('|', "\\vert{}"));
+    let widths = widths(book.table(), &rows);
+    let mut text = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        text += &write_row(row, &widths);
+        if i == 0 && rows.len() > 1 {
+            let dashes: Vec<String> = widths.iter().map(|w| "-".repeat(w + 2)).collect();
+            text += &format!("|{}|\n", dashes.join("+"));
+        }
+    }
+    return text;
+}
+
+///Writes an AsciiDoc table, the first row is the header
+pub fn write_asciidoc(book: &Workbook, contents: Contents) -> String {
+    let rows = cells(book, contents, escape_pipe);
+    let widths = widths(book.table(), &rows);
+    let mut text = String::from("[%header]\n|===\n");
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("| {:<width$}", cell, width = width))
+            .collect();
+        text += &format!("{}\n", cells.join(" ").trim_end());
+        //the header is followed by a blank line
+        if i == 0 {
+            text += "\n";
+        }
+    }
+    return text + "|===\n";
+}
+
+///Splits a table line into its cells, `\|` is a | inside a cell
+fn split_row(line: &str) -> Vec<String> {
+    let inner = line.trim().trim_start_matches('|');
+    let inner = match inner.strip_suffix('|') {
+        Some(i) if !i.ends_with('\\') => i,
+        _ => inner,
+    };
+    let mut cells: Vec<String> = vec![];
+    let mut cell = String::new();
+    let mut chars = inner.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell += "|";
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell)),
+            _ => cell += &String::from(ch),
+        }
+    }
+    cells.push(cell);
+    return cells
+        .iter()
+        .map(|c| c.trim().replace("\\vert{}", "|"))
+        .collect();
+}
+
+///Whether a line is the rule under a header, as in `| --- | :-: |` or `|---+---|`
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    return line.contains('-')
+        && line
+            .chars()
+            .all(|c| c == '|' || c == '-' || c == ':' || c == '+' || c == ' ');
+}
+
+///Whether a line only separates rows, as an org hline `|---+---|` or an asciidoc fence `|===`.
+///Rows of dashes with spaces, as in `| - | - |`, are data
+fn is_separator(line: &str) -> bool {
+    let line = line.trim();
+    let hline = line.starts_with("|-") && line.chars().all(|c| c == '|' || c == '-' || c == '+');
+    return hline || line == "|===";
+}
+
+///Reads the lines of a markdown or org table, lines that are not part of a table are skipped so
+///a table can be pasted with the text around it. A header row is kept as text and frozen
+pub fn read(bytes: &[u8], locale: Locale) -> (Table, Vec<String>) {
+    let text = String::from_utf8_lossy(bytes);
+    let mut lines: Vec<&str> = text
+        .lines()
+        .filter(|l| l.trim_start().starts_with('|'))
+        .collect();
+    let header = lines.len() > 1 && is_rule(lines[1]);
+    if header {
+        lines.remove(1);
+    }
+    lines.retain(|l| !is_separator(l));
+    let mut rows: Vec<Vec<Data>> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let row = split_row(line).into_iter().map(|cell| {
+            if header && i == 0 {
+                return Data::String(cell);
+            }
+            return data_from_field(&cell, &locale);
+        });
+        rows.push(row.collect());
+    }
+    let mut errors: Vec<String> = vec![];
+    if rows.len() == 0 {
+        errors.push("No table found, table rows start with |".to_string());
+    }
+    let mut table = Table::from_rows(rows, locale);
+    if header {
+        table.get_settings_mut().frozen_rows = 1;
+    }
+    return (table, errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::cell_texts;
+
+    fn texts(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        return rows
+            .iter()
+            .map(|row| row.iter().map(|t| t.to_string()).collect())
+            .collect();
+    }
+
+    #[test]
+    fn reads_a_markdown_table() {
+        let text = "Some text\n| a | b |\n| --- | :-: |\n| 1 | x\\|y |\n";
+        let (table, errors) = read(text.as_bytes(), Locale::canonical());
+        assert!(errors.is_empty());
+        assert_eq!(cell_texts(&table), texts(&[&["a", "b"], &["1", "x|y"]]));
+        assert_eq!(table.get_settings().frozen_rows, 1);
+    }
+
+    #[test]
+    fn keeps_body_rows_of_dashes() {
+        let text = "| a | b |\n| --- | --- |\n| - | - |\n| 1 | 2 |\n";
+        let (table, _) = read(text.as_bytes(), Locale::canonical());
+        assert_eq!(cell_texts(&table), texts(&[&["a", "b"], &["-", "-"], &["1", "2"]]));
+    }
+
+    #[test]
+    fn skips_org_hlines() {
+        let text = "| a | b |\n|---+---|\n| 1 | 2 |\n|---+---|\n| 3 | 4 |\n";
+        let (table, _) = read(text.as_bytes(), Locale::canonical());
+        assert_eq!(cell_texts(&table), texts(&[&["a", "b"], &["1", "2"], &["3", "4"]]));
+    }
+
+    #[test]
+    fn writes_markdown_that_reads_back() {
+        let rows = vec![
+            vec![Data::String("name".to_string()), Data::String("a|b".to_string())],
+            vec![Data::Number("1".to_string()), Data::String("-".to_string())],
+        ];
+        let book = Workbook::new(Table::from_rows(rows, Locale::canonical()));
+        let text = write_markdown(&book, Contents::Values);
+        assert!(text.starts_with("| name       | a\\|b       |\n| ---------- |"));
+        let (table, _) = read(text.as_bytes(), Locale::canonical());
+        assert_eq!(cell_texts(&table), cell_texts(book.table()));
+    }
+}
@@ -0,0 +1,356 @@
+pub mod csv;
+pub mod fixed;
+pub mod html;
+pub mod json;
+pub mod latex;
+pub mod markup;
+pub mod ods;
+pub mod spreadsheet;
+pub mod sql;
+pub mod sqlite;
+
+use std::collections::HashMap;
+
+use encoding_rs::Encoding;
+
+use crate::{
+    locale::Locale,
+    sheet_tokenizer,
+    table::{Data, Position, Table},
+    workbook::Workbook,
+};
+
+///A file format sheets can be opened from and saved in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Tsheet,
+    Csv,
+    ///Csv with tabs between fields
+    Tsv,
+    ///An array of objects, one for each row after the header
+    Json,
+    ///JSON Lines, an object on each line
+    Jsonl,
+    ///A GitHub flavoured markdown table
+    Markdown,
+    Org,
+    Asciidoc,
+    ///A `<table>` element
+    Html,
+    ///A LaTeX tabular using booktabs rules
+    Latex,
+    ///An Excel workbook
+    Xlsx,
+    ///An OpenDocument spreadsheet
+    Ods,
+    ///A script of `CREATE TABLE` and `INSERT` statements
+    Sql,
+    ///Text in columns of fixed width, such as reports or the output of `ps`
+    Fixed,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        return match name.to_lowercase().as_str() {
+            "tsheet" => Some(Format::Tsheet),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "json" => Some(Format::Json),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            "markdown" | "md" => Some(Format::Markdown),
+            "org" => Some(Format::Org),
+            "asciidoc" | "adoc" => Some(Format::Asciidoc),
+            "html" | "htm" => Some(Format::Html),
+            "latex" | "tex" => Some(Format::Latex),
+            "xlsx" => Some(Format::Xlsx),
+            "ods" => Some(Format::Ods),
+            "sql" => Some(Format::Sql),
+            "fixed" => Some(Format::Fixed),
+            _ => None,
+        };
+    }
+
+    ///The format for a file's extension, None if the extension is not known
+    pub fn from_path(path: &str) -> Option<Format> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        return Format::from_name(extension);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Format::Tsheet => "tsheet",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Json => "json",
+            Format::Jsonl => "jsonl",
+            Format::Markdown => "markdown",
+            Format::Org => "org",
+            Format::Asciidoc => "asciidoc",
+            Format::Html => "html",
+            Format::Latex => "latex",
+            Format::Xlsx => "xlsx",
+            Format::Ods => "ods",
+            Format::Sql => "sql",
+            Format::Fixed => "fixed",
+        };
+    }
+
+    ///The extension of files written in this format
+    pub fn extension(&self) -> &'static str {
+        return match self {
+            Format::Markdown => "md",
+            Format::Asciidoc => "adoc",
+            Format::Latex => "tex",
+            Format::Fixed => "txt",
+            _ => self.name(),
+        };
+    }
+
+    ///Whether the format can hold equations, sheets without equations are not changed by saving
+    pub fn keeps_equations(&self) -> bool {
+        return *self == Format::Tsheet;
+    }
+
+    ///Whether every sheet of a workbook is saved, rather than only the sheet being edited
+    pub fn keeps_sheets(&self) -> bool {
+        return matches!(self, Format::Tsheet | Format::Xlsx | Format::Ods);
+    }
+
+    ///Whether equations are saved as spreadsheet formulas where they can be translated
+    pub fn is_spreadsheet(&self) -> bool {
+        return matches!(self, Format::Xlsx | Format::Ods);
+    }
+}
+
+///What is written for equations in formats without equations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Contents {
+    ///The result of the equation, as shown in the sheet
+    Values,
+    ///The equation's text after `=`
+    Formulas,
+}
+
+///How a file is read
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    ///The delimiter, quote, header and encoding of csv files, the encoding is also used for fixed
+    ///width text
+    pub csv: csv::CsvOptions,
+    ///The width of each column of fixed width text, guessed from the text if not given
+    pub widths: Option<Vec<usize>>,
+}
+
+impl ReadOptions {
+    ///Reads the options given on the command line
+    pub fn from_args(opts: &HashMap<String, String>, locale: &Locale) -> Result<ReadOptions, String> {
+        let widths = match opts.get("--widths") {
+            None => None,
+            Some(w) => Some(fixed::parse_widths(w).ok_or(format!("Invalid widths: {}", w))?),
+        };
+        return Ok(ReadOptions {
+            csv: csv::CsvOptions::from_args(opts, locale)?,
+            widths,
+        });
+    }
+}
+
+///How a sheet is written
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub contents: Contents,
+    ///The delimiter, quote and encoding of csv files
+    pub csv: csv::CsvOptions,
+    ///Gives each html cell a class for the kind of value in it
+    pub html_classes: bool,
+    ///The table sql scripts create, the sheet's name if not given
+    pub table_name: Option<String>,
+}
+
+impl WriteOptions {
+    ///Writes values, and csv files with the given options
+    pub fn new(csv: csv::CsvOptions) -> WriteOptions {
+        return WriteOptions {
+            contents: Contents::Values,
+            csv,
+            html_classes: false,
+            table_name: None,
+        };
+    }
+}
+
+///The kind of value shown in a cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Number,
+    Text,
+    ///An equation that could not be evaluated
+    Error,
+}
+
+impl ValueKind {
+    pub fn of(book: &Workbook, pos: &Position, data: &Data) -> ValueKind {
+        type V = crate::calculator::CalculatorValue;
+        match data.evaluate(book, pos) {
+            None => match data {
+                Data::Number(..) => return ValueKind::Number,
+                _ => return ValueKind::Text,
+            },
+            Some(Ok(V::Number(..) | V::Decimal(..) | V::Quantity(..))) => return ValueKind::Number,
+            Some(Ok(..)) => return ValueKind::Text,
+            Some(Err(..)) => return ValueKind::Error,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ValueKind::Number => "number",
+            ValueKind::Text => "text",
+            ValueKind::Error => "error",
+        };
+    }
+}
+
+impl Contents {
+    pub fn from_name(name: &str) -> Option<Contents> {
+        return match name {
+            "values" => Some(Contents::Values),
+            "formulas" => Some(Contents::Formulas),
+            _ => None,
+        };
+    }
+}
+
+///The text written for a cell in formats without equations, equations are written as their
+///value, or as their text after `=`
+pub fn cell_text(book: &Workbook, pos: &Position, data: &Data, contents: Contents) -> String {
+    match data {
+        Data::Equation(e, ..) if contents == Contents::Formulas => return format!("={}", e),
+        _ => return data.value_text(book, pos),
+    }
+}
+
+///Reads a file in format, problems are returned instead of stopping the load
+pub fn read(
+    bytes: &[u8],
+    format: Format,
+    options: &ReadOptions,
+    locale: Locale,
+) -> (Workbook, Vec<String>) {
+    let (table, errors) = match format {
+        Format::Tsheet => {
+            let text = String::from_utf8_lossy(bytes);
+            let (mut book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(&text));
+            book.set_locale(locale);
+            return (book, errors.iter().map(|e| e.to_string()).collect());
+        }
+        Format::Xlsx | Format::Ods => return spreadsheet::read(bytes, locale),
+        Format::Csv => csv::read(bytes, &options.csv, locale),
+        Format::Tsv => {
+            let options = csv::CsvOptions {
+                delimiter: b'\t',
+                ..options.csv
+            };
+            csv::read(bytes, &options, locale)
+        }
+        Format::Json | Format::Jsonl => json::read(bytes, format == Format::Jsonl, locale),
+        Format::Markdown | Format::Org => markup::read(bytes, locale),
+        Format::Fixed => {
+            let text = decode(bytes, options.csv.encoding);
+            fixed::read(&text, options.widths.as_ref(), locale)
+        }
+        Format::Asciidoc | Format::Html | Format::Latex | Format::Sql => {
+            let table = Table::from_rows(vec![], locale);
+            let error = format!("{} files can only be exported", format.name());
+            (table, vec![error])
+        }
+    };
+    return (Workbook::new(table), errors);
+}
+
+///Writes the sheet being edited in format, only tsheet files hold every sheet of a workbook and
+///its settings, and spreadsheets every sheet
+pub fn write(book: &Workbook, format: Format, options: &WriteOptions) -> Result<Vec<u8>, String> {
+    let contents = options.contents;
+    let text = match format {
+        Format::Tsheet => book.to_sheet(),
+        Format::Csv => return Ok(csv::write(book, &options.csv, contents)),
+        Format::Tsv => {
+            let csv_options = csv::CsvOptions {
+                delimiter: b'\t',
+                ..options.csv
+            };
+            return Ok(csv::write(book, &csv_options, contents));
+        }
+        Format::Json | Format::Jsonl => json::write(book, format == Format::Jsonl, contents),
+        Format::Markdown => markup::write_markdown(book, contents),
+        Format::Org => markup::write_org(book, contents),
+        Format::Asciidoc => markup::write_asciidoc(book, contents),
+        Format::Html => html::write(book, contents, options.html_classes),
+        Format::Latex => latex::write(book, contents),
+        Format::Xlsx => return spreadsheet::write_xlsx(book),
+        Format::Ods => return ods::write(book),
+        Format::Fixed => fixed::write(book, contents),
+        Format::Sql => {
+            let name = match &options.table_name {
+                Some(name) => name.clone(),
+                None => book.sheet_name(book.active_index()),
+            };
+            sql::write(book, &name, contents)
+        }
+    };
+    return Ok(text.into_bytes());
+}
+
+///Opens a table from a source other than a file, as in `sqlite:data.db?table=people`. Errors
+///that leave nothing to open are returned as Err
+pub fn import(source: &str, locale: Locale) -> Result<(Workbook, Vec<String>), String> {
+    let usage = "Usage: --import sqlite:<path>?table=<name>";
+    let (kind, location) = source.split_once(':').ok_or(usage)?;
+    if kind != "sqlite" {
+        return Err(format!("Unknown import source: {}", kind));
+    }
+    let (path, query) = location.split_once('?').ok_or(usage)?;
+    let table = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("table="))
+        .ok_or(usage)?;
+    let (table, errors) = sqlite::read(path, table, locale)?;
+    return Ok((Workbook::new(table), errors));
+}
+
+///Converts a field read from another format into a cell, numbers written in the locale are
+///stored in canonical form
+pub fn data_from_field(field: &str, locale: &Locale) -> Data {
+    match locale.parse_number(field) {
+        Some(n) => Data::Number(n),
+        None => Data::String(field.to_string()),
+    }
+}
+
+///Finds an encoding by a label such as `utf-8`, `latin1` or `windows-1252`
+pub fn parse_encoding(label: &str) -> Option<&'static Encoding> {
+    return Encoding::for_label(label.trim().as_bytes());
+}
+
+///Decodes bytes read from a file, a byte order mark overrides the encoding
+pub fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    let (text, _, _) = encoding.decode(bytes);
+    return text.into_owned();
+}
+
+///The text of every cell, equations as their text, for comparing sheets in tests
+#[cfg(test)]
+pub fn cell_texts(table: &Table) -> Vec<Vec<String>> {
+    return table
+        .get_rows()
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|data| match data {
+                    Data::Number(t) | Data::String(t) | Data::Equation(t, ..) => t.clone(),
+                })
+                .collect()
+        })
+        .collect();
+}
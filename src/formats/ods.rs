@@ -0,0 +1,168 @@
+use std::io::{Cursor, Write};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use super::spreadsheet::{cell_value, formula_from_equation, Dialect, Value};
+use crate::table::{Data, Position};
+use crate::workbook::Workbook;
+
+const MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+ <manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+ <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+fn escape(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+///The style of a column that fits width characters, LibreOffice's default column of 2.258cm fits
+///about 10
+fn column_style(width: usize) -> String {
+    return format!(
+        "<style:style style:name=\"co{}\" style:family=\"table-column\">\
+         <style:table-column-properties style:column-width=\"{:.3}cm\"/></style:style>\n",
+        width,
+        width as f64 * 0.2258
+    );
+}
+
+///Writes a cell, with its formula when the equation can be translated
+fn write_cell(book: &Workbook, index: usize, pos: &Position, data: &Data) -> String {
+    let formula = match data {
+        Data::Equation(e, ..) => formula_from_equation(e, Dialect::OpenFormula)
+            .map(|f| format!(" table:formula=\"of:={}\"", escape(&f))),
+        _ => None,
+    };
+    let formula = formula.unwrap_or_default();
+    let (value, text) = match cell_value(book, index, pos, data) {
+        Value::Number(n) => (
+            format!("office:value-type=\"float\" office:value=\"{}\"", n),
+            n.to_string(),
+        ),
+        Value::Text(t) if t.is_empty() && formula.is_empty() => {
+            return "<table:table-cell/>".to_string()
+        }
+        Value::Text(t) => ("office:value-type=\"string\"".to_string(), t),
+    };
+    let paragraphs: Vec<String> = text
+        .lines()
+        .map(|line| format!("<text:p>{}</text:p>", escape(line)))
+        .collect();
+    return format!(
+        "<table:table-cell {}{}>{}</table:table-cell>",
+        value,
+        formula,
+        paragraphs.join("")
+    );
+}
+
+///The content.xml of every sheet
+fn content(book: &Workbook) -> String {
+    let mut widths: Vec<usize> = vec![];
+    let mut tables = String::new();
+    for index in 0..book.len() {
+        let sheet = book.sheet(index);
+        tables += &format!(
+            "<table:table table:name=\"{}\">\n",
+            escape(&book.sheet_name(index))
+        );
+        for col in 0..sheet.get_size()[1] {
+            let width = sheet.get_col_width(col).unwrap_or(10);
+            if !widths.contains(&width) {
+                widths.push(width);
+            }
+            tables += &format!("<table:table-column table:style-name=\"co{}\"/>\n", width);
+        }
+        for (row_no, row) in sheet.get_rows().iter().enumerate() {
+            tables += "<table:table-row>";
+            for (col_no, data) in row.iter().enumerate() {
+                let pos = Position {
+                    row: row_no,
+                    col: col_no,
+                };
+                tables += &write_cell(book, index, &pos, data);
+            }
+            tables += "</table:table-row>\n";
+        }
+        tables += "</table:table>\n";
+    }
+    let styles: String = widths.into_iter().map(column_style).collect();
+    return format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:of="urn:oasis:names:tc:opendocument:xmlns:of:1.2" office:version="1.2">
+<office:automatic-styles>
+{}</office:automatic-styles>
+<office:body><office:spreadsheet>
+{}</office:spreadsheet></office:body>
+</office:document-content>
+"#,
+        styles, tables
+    );
+}
+
+///Writes every sheet as a table of an ods file. Equations are written as formulas along with their
+///result, equations that cannot be translated are written as their result
+pub fn write(book: &Workbook) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    //the mimetype must come first and not be compressed so the file type can be read from it
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default();
+    let files = [
+        ("mimetype", MIMETYPE.to_string(), stored),
+        ("META-INF/manifest.xml", MANIFEST.to_string(), deflated),
+        ("content.xml", content(book), deflated),
+    ];
+    for (name, text, options) in files {
+        zip.start_file(name, options).map_err(|e| e.to_string())?;
+        zip.write_all(text.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let cursor = zip.finish().map_err(|e| e.to_string())?;
+    return Ok(cursor.into_inner());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{cell_texts, spreadsheet};
+    use crate::locale::Locale;
+    use crate::sheet_tokenizer;
+
+    fn book(text: &str) -> Workbook {
+        let (book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        assert!(errors.is_empty(), "{:?}", errors);
+        return book;
+    }
+
+    #[test]
+    fn writes_formulas_with_their_values() {
+        let book = book("#!tsheet 2\n[\"a < b\",2,(3 * $B1)]\n");
+        let text = content(&book);
+        assert!(text.contains("<text:p>a &lt; b</text:p>"));
+        assert!(text.contains("office:value=\"6\" table:formula=\"of:=3*[.B1]\""));
+    }
+
+    #[test]
+    fn writes_sheets_that_read_back() {
+        let book = book(
+            "#!tsheet 2\n%name Summary\n[\"total\",(sum(Costs!$A1:$A2))]\n\n\
+             %sheet Costs\n[12,]\n[(Summary!$A1*2),]\n",
+        );
+        let bytes = write(&book).unwrap();
+        let (read, errors) = spreadsheet::read(&bytes, Locale::canonical());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.sheet_name(0), "Summary");
+        assert_eq!(read.sheet_name(1), "Costs");
+        for index in 0..2 {
+            assert_eq!(cell_texts(read.sheet(index)), cell_texts(book.sheet(index)));
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::io::Cursor;
+
+use calamine::Reader;
+use rust_xlsxwriter::Formula;
+
+use crate::{
+    calculator::{self, CalculatorValue, Token},
+    locale::Locale,
+    table::{error_text, parse_position_name, position_name, Data, Position, Table},
+    workbook::Workbook,
+};
+
+///How references and argument separators are written in a spreadsheet program's formulas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    ///As in `SUM(A1:B2,Rates!C3)`
+    Excel,
+    ///OpenDocument formulas, as in `SUM([.A1:.B2];[Rates.C3])`
+    OpenFormula,
+}
+
+///Functions that work the same way in sheets and in spreadsheet programs, by their name in each
+const FUNCTIONS: [(&str, &str); 4] = [
+    ("sum", "SUM"),
+    ("mean", "AVERAGE"),
+    ("rand", "RAND"),
+    ("randbetween", "RANDBETWEEN"),
+];
+
+///A cell once equations are evaluated
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+///Whether name can be used before `!` in a reference without quotes
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    return matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+///Splits a reference such as `$A1` or `rates!$B4` into its sheet and cell, None if name is not a
+///cell
+fn split_reference(name: &str) -> Option<(Option<&str>, Position)> {
+    let (sheet, cell) = match name.split_once('!') {
+        Some((sheet, cell)) => (Some(sheet), cell),
+        None => (None, name),
+    };
+    if !cell.starts_with('$') {
+        return None;
+    }
+    return Some((sheet, parse_position_name(cell)?));
+}
+
+///Writes a reference to a cell, or to a range when there is an end, as dialect writes it
+fn write_reference(
+    sheet: Option<&str>,
+    start: &Position,
+    end: Option<&Position>,
+    dialect: Dialect,
+) -> String {
+    match dialect {
+        Dialect::Excel => {
+            let sheet = sheet.map_or(String::new(), |s| format!("{}!", s));
+            return match end {
+                Some(end) => format!("{}{}:{}", sheet, position_name(start), position_name(end)),
+                None => format!("{}{}", sheet, position_name(start)),
+            };
+        }
+        Dialect::OpenFormula => {
+            let sheet = sheet.unwrap_or("");
+            return match end {
+                Some(end) => format!("[{}.{}:.{}]", sheet, position_name(start), position_name(end)),
+                None => format!("[{}.{}]", sheet, position_name(start)),
+            };
+        }
+    }
+}
+
+///Translates an equation into a formula without the leading `=`, None if the equation uses
+///something spreadsheet programs do not have such as units or named ranges
+pub fn formula_from_equation(equation: &str, dialect: Dialect) -> Option<String> {
+    let tokens = calculator::get_tokens(equation);
+    let mut formula = String::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Plus => formula += "+",
+            Token::Minus => formula += "-",
+            Token::Star => formula += "*",
+            Token::Div => formula += "/",
+            Token::LParen => formula += "(",
+            Token::RParen => formula += ")",
+            Token::Comma if dialect == Dialect::Excel => formula += ",",
+            Token::Comma => formula += ";",
+            Token::Number(n) => formula += &n.to_string(),
+            Token::String(s) => formula += &format!("\"{}\"", s.replace('"', "\"\"")),
+            Token::Ident(name) if matches!(tokens.get(i + 1), Some(Token::LParen)) => {
+                let (_, function) = FUNCTIONS
+                    .iter()
+                    .find(|(ours, _)| name.eq_ignore_ascii_case(ours))?;
+                formula += function;
+            }
+            Token::Ident(name) => {
+                let (sheet, start) = split_reference(name)?;
+                let end = match (tokens.get(i + 1), tokens.get(i + 2)) {
+                    (Some(Token::Colon), Some(Token::Ident(end))) => {
+                        let (end_sheet, end) = split_reference(end)?;
+                        if end_sheet.is_some() && end_sheet != sheet {
+                            return None;
+                        }
+                        i += 2;
+                        Some(end)
+                    }
+                    _ => None,
+                };
+                formula += &write_reference(sheet, &start, end.as_ref(), dialect);
+            }
+            Token::End => {}
+            _ => return None,
+        }
+        i += 1;
+    }
+    return Some(formula);
+}
+
+///Reads text in quotes starting at i, where a doubled quote is a quote inside the text. Returns the
+///text and where the text after the closing quote starts
+fn read_quoted(chars: &[char], mut i: usize) -> Option<(String, usize)> {
+    let quote = chars[i];
+    let mut text = String::new();
+    i += 1;
+    loop {
+        match chars.get(i)? {
+            c if *c == quote && chars.get(i + 1) == Some(&quote) => {
+                text.push(quote);
+                i += 2;
+            }
+            c if *c == quote => return Some((text, i + 1)),
+            c => {
+                text.push(*c);
+                i += 1;
+            }
+        }
+    }
+}
+
+///Reads a name such as `A1`, `SUM`, `Rates!$C$3` or `'Tax rates'!C3` starting at i. Returns the
+///sheet, the name and where the text after the name starts
+fn read_name(chars: &[char], mut i: usize) -> Option<(Option<String>, String, usize)> {
+    let word_end = |from: usize| {
+        (from..chars.len())
+            .find(|j| !(chars[*j].is_alphanumeric() || "_$.".contains(chars[*j])))
+            .unwrap_or(chars.len())
+    };
+    let mut sheet = None;
+    if chars[i] == '\'' {
+        let (name, end) = read_quoted(chars, i)?;
+        if chars.get(end) != Some(&'!') {
+            return None;
+        }
+        sheet = Some(name);
+        i = end + 1;
+    }
+    let mut end = word_end(i);
+    let mut name: String = chars[i..end].iter().collect();
+    if sheet.is_none() && chars.get(end) == Some(&'!') {
+        sheet = Some(name);
+        i = end + 1;
+        end = word_end(i);
+        name = chars[i..end].iter().collect();
+    }
+    return Some((sheet, name, end));
+}
+
+///Rewrites an OpenDocument formula with its references and argument separators written as in
+///Excel, `[.A1:.B2];[Rates.C3]` becomes `A1:B2,Rates!C3`
+fn excel_from_openformula(formula: &str) -> Option<String> {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut excel = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                let (_, end) = read_quoted(&chars, i)?;
+                excel.extend(&chars[i..end]);
+                i = end;
+            }
+            ';' => {
+                excel.push(',');
+                i += 1;
+            }
+            '[' => {
+                let end = i + chars[i..].iter().position(|c| *c == ']')?;
+                let reference: String = chars[i + 1..end].iter().collect();
+                let mut parts: Vec<String> = vec![];
+                for part in reference.split(':') {
+                    let (sheet, cell) = part.rsplit_once('.')?;
+                    let sheet = sheet.trim_start_matches('$');
+                    //the end of a range is in the same sheet as its start
+                    if parts.len() > 0 || sheet.is_empty() {
+                        parts.push(cell.to_string());
+                    } else {
+                        parts.push(format!("{}!{}", sheet, cell));
+                    }
+                }
+                excel += &parts.join(":");
+                i = end + 1;
+            }
+            c => {
+                excel.push(c);
+                i += 1;
+            }
+        }
+    }
+    return Some(excel);
+}
+
+///Translates a formula read from an xlsx or ods file into an equation, None if the formula uses
+///something sheets do not have
+pub fn equation_from_formula(formula: &str) -> Option<String> {
+    let formula = formula.trim();
+    let formula = match formula.strip_prefix("of:") {
+        Some(f) => excel_from_openformula(f)?,
+        None => formula.to_string(),
+    };
+    let chars: Vec<char> = formula.strip_prefix('=').unwrap_or(&formula).chars().collect();
+    let mut equation = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '"' {
+            let (text, end) = read_quoted(&chars, i)?;
+            let text = text
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            equation += &format!("\"{}\"", text);
+            i = end;
+        } else if "+-*/(),: ".contains(ch) {
+            equation.push(ch);
+            i += 1;
+        } else if ch.is_ascii_digit() || ch == '.' {
+            let end = (i..chars.len())
+                .find(|j| !(chars[*j].is_ascii_digit() || chars[*j] == '.'))
+                .unwrap_or(chars.len());
+            //exponents and percentages
+            if matches!(chars.get(end), Some(c) if c.is_alphabetic() || *c == '%') {
+                return None;
+            }
+            equation.extend(&chars[i..end]);
+            i = end;
+        } else if ch == '\'' || ch == '$' || ch == '_' || ch.is_alphabetic() {
+            let (sheet, name, end) = read_name(&chars, i)?;
+            let is_call = chars[end..].iter().find(|c| **c != ' ') == Some(&'(');
+            if is_call && sheet.is_none() {
+                let (function, _) = FUNCTIONS
+                    .iter()
+                    .find(|(_, theirs)| name.eq_ignore_ascii_case(theirs))?;
+                equation += function;
+            } else {
+                let pos = parse_position_name(&name.replace('$', ""))?;
+                if let Some(sheet) = sheet {
+                    if !is_identifier(&sheet) {
+                        return None;
+                    }
+                    equation += &format!("{}!", sheet);
+                }
+                equation += &format!("${}", position_name(&pos));
+            }
+            i = end;
+        } else {
+            return None;
+        }
+    }
+    return Some(equation);
+}
+
+///Converts a value saved in a spreadsheet into a cell, text is kept as text even if it looks like
+///a number
+fn data_from_cell(value: &calamine::Data) -> Data {
+    type C = calamine::Data;
+    match value {
+        C::Int(n) => return Data::Number(n.to_string()),
+        C::Float(n) => return Data::Number(n.to_string()),
+        C::Bool(b) => return Data::String(b.to_string().to_uppercase()),
+        C::DateTime(d) if d.is_duration() => {
+            let seconds = (d.as_f64() * 86400.0).round() as i64;
+            return Data::String(format!(
+                "{}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ));
+        }
+        C::DateTime(d) => {
+            let (year, month, day, hour, minute, second, _) = d.to_ymd_hms_milli();
+            let date = format!("{:04}-{:02}-{:02}", year, month, day);
+            if (hour, minute, second) == (0, 0, 0) {
+                return Data::String(date);
+            }
+            return Data::String(format!("{} {:02}:{:02}:{:02}", date, hour, minute, second));
+        }
+        C::Empty => return Data::String(String::new()),
+        _ => return Data::String(value.to_string()),
+    }
+}
+
+///Reads every sheet of an xlsx or ods file. Formulas that cannot be translated are read as the
+///value the spreadsheet program saved for them, and listed in the returned problems
+pub fn read(bytes: &[u8], locale: Locale) -> (Workbook, Vec<String>) {
+    let mut workbook = match calamine::open_workbook_auto_from_rs(Cursor::new(bytes.to_vec())) {
+        Ok(w) => w,
+        Err(e) => {
+            let error = format!("Could not read spreadsheet: {}", e);
+            return (Workbook::new(Table::from_rows(vec![], locale)), vec![error]);
+        }
+    };
+    let mut errors: Vec<String> = vec![];
+    let mut sheets: Vec<Table> = vec![];
+    for name in workbook.sheet_names() {
+        let values = match workbook.worksheet_range(&name) {
+            Ok(v) => v,
+            Err(e) => {
+                errors.push(format!("Could not read {}: {}", name, e));
+                continue;
+            }
+        };
+        let formulas = workbook.worksheet_formula(&name).unwrap_or_default();
+        //cells are read from A1 so that references in formulas still point at them
+        let end = |range_end: Option<(u32, u32)>| range_end.map_or((0, 0), |(r, c)| (r + 1, c + 1));
+        let (value_rows, value_cols) = end(values.end());
+        let (formula_rows, formula_cols) = end(formulas.end());
+        let mut rows: Vec<Vec<Data>> = vec![];
+        for row in 0..value_rows.max(formula_rows) {
+            let mut cells: Vec<Data> = vec![];
+            for col in 0..value_cols.max(formula_cols) {
+                let value = values.get_value((row, col)).unwrap_or(&calamine::Data::Empty);
+                let formula = formulas.get_value((row, col)).filter(|f| f.len() > 0);
+                let data = match formula.map(|f| (f, equation_from_formula(f))) {
+                    None => data_from_cell(value),
+                    Some((_, Some(equation))) => Data::equation(equation, None),
+                    Some((formula, None)) => {
+                        let pos = Position {
+                            row: row as usize,
+                            col: col as usize,
+                        };
+                        let formula = formula.trim_start_matches("of:").trim_start_matches('=');
+                        errors.push(format!(
+                            "{}!{}: kept the value of ={}",
+                            name,
+                            position_name(&pos),
+                            formula
+                        ));
+                        data_from_cell(value)
+                    }
+                };
+                cells.push(data);
+            }
+            rows.push(cells);
+        }
+        let mut table = Table::from_rows(rows, locale);
+        //sheets named the way sheets without a name are shown stay without a name
+        if name != format!("Sheet{}", sheets.len() + 1) {
+            table.get_settings_mut().name = Some(name);
+        }
+        sheets.push(table);
+    }
+    let mut sheets = sheets.into_iter();
+    let mut book = Workbook::new(
+        sheets
+            .next()
+            .unwrap_or_else(|| Table::from_rows(vec![], locale)),
+    );
+    for sheet in sheets {
+        book.push_sheet(sheet);
+    }
+    return (book, errors);
+}
+
+///The value of a cell in the sheet at index, with equations evaluated
+pub fn cell_value(book: &Workbook, index: usize, pos: &Position, data: &Data) -> Value {
+    match data {
+        Data::Number(n) => return n.parse().map_or(Value::Text(n.clone()), Value::Number),
+        Data::String(s) => return Value::Text(s.clone()),
+        Data::Equation(..) => {}
+    }
+    match book.evaluate_in_sheet(index, pos) {
+        Some(Ok(CalculatorValue::Number(n))) => return Value::Number(n),
+        Some(Ok(CalculatorValue::Decimal(d))) => return Value::Number(d.to_f64()),
+        Some(Ok(v)) => return Value::Text(book.sheet(index).format_result_at(pos, &v)),
+        Some(Err(e)) => return Value::Text(error_text(&e).to_string()),
+        None => return Value::Text(String::new()),
+    }
+}
+
+///The number of equations in every sheet that cannot be written as spreadsheet formulas
+pub fn untranslatable(book: &Workbook) -> usize {
+    let mut count = 0;
+    for index in 0..book.len() {
+        for row in book.sheet(index).get_rows() {
+            count += row
+                .iter()
+                .filter(|data| match data {
+                    Data::Equation(e, ..) => formula_from_equation(e, Dialect::Excel).is_none(),
+                    _ => false,
+                })
+                .count();
+        }
+    }
+    return count;
+}
+
+///Writes every sheet as a worksheet of an xlsx file. Equations are written as formulas along with
+///their result, equations that cannot be translated are written as their result
+pub fn write_xlsx(book: &Workbook) -> Result<Vec<u8>, String> {
+    let mut workbook = rust_xlsxwriter::Workbook::new();
+    for index in 0..book.len() {
+        let sheet = book.sheet(index);
+        let worksheet = workbook.add_worksheet();
+        worksheet
+            .set_name(book.sheet_name(index))
+            .map_err(|e| e.to_string())?;
+        //both count widths in characters
+        for col in 0..sheet.get_size()[1] {
+            if let Some(width) = sheet.get_col_width(col) {
+                worksheet
+                    .set_column_width(col as u16, width as f64)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        let settings = sheet.get_settings();
+        if settings.frozen_rows > 0 || settings.frozen_cols > 0 {
+            worksheet
+                .set_freeze_panes(settings.frozen_rows as u32, settings.frozen_cols as u16)
+                .map_err(|e| e.to_string())?;
+        }
+        for (row_no, row) in sheet.get_rows().iter().enumerate() {
+            for (col_no, data) in row.iter().enumerate() {
+                let pos = Position {
+                    row: row_no,
+                    col: col_no,
+                };
+                let (r, c) = (row_no as u32, col_no as u16);
+                let formula = match data {
+                    Data::Equation(e, ..) => formula_from_equation(e, Dialect::Excel),
+                    _ => None,
+                };
+                let written = match (formula, cell_value(book, index, &pos, data)) {
+                    (Some(f), Value::Number(n)) => {
+                        worksheet.write_formula(r, c, Formula::new(f).set_result(n.to_string()))
+                    }
+                    (Some(f), Value::Text(t)) => {
+                        worksheet.write_formula(r, c, Formula::new(f).set_result(t))
+                    }
+                    (None, Value::Number(n)) => worksheet.write_number(r, c, n),
+                    (None, Value::Text(t)) if t.is_empty() => continue,
+                    (None, Value::Text(t)) => worksheet.write_string(r, c, t),
+                };
+                written.map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    return workbook.save_to_buffer().map_err(|e| e.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::cell_texts;
+    use crate::sheet_tokenizer;
+
+    #[test]
+    fn translates_equations_to_formulas() {
+        let equation = "sum($a1:$b2, rates!$C3) * -2";
+        assert_eq!(
+            formula_from_equation(equation, Dialect::Excel),
+            Some("SUM(A1:B2,rates!C3)*-2".to_string())
+        );
+        assert_eq!(
+            formula_from_equation(equation, Dialect::OpenFormula),
+            Some("SUM([.A1:.B2];[rates.C3])*-2".to_string())
+        );
+        assert_eq!(formula_from_equation("1 m + $A1", Dialect::Excel), None);
+        assert_eq!(formula_from_equation("convert($A1, \"mm\")", Dialect::Excel), None);
+    }
+
+    #[test]
+    fn translates_formulas_to_equations() {
+        assert_eq!(
+            equation_from_formula("=AVERAGE(A1:B2,'rates'!$C$3)"),
+            Some("mean($A1:$B2,rates!$C3)".to_string())
+        );
+        assert_eq!(
+            equation_from_formula("of:=SUM([.A1:.B2];[rates.C3])"),
+            Some("sum($A1:$B2,rates!$C3)".to_string())
+        );
+        assert_eq!(equation_from_formula("=VLOOKUP(A1,B1:C2,2)"), None);
+        assert_eq!(equation_from_formula("=1E3+A1"), None);
+    }
+
+    #[test]
+    fn writes_xlsx_that_reads_back() {
+        let text = "#!tsheet 2\n[\"name\",2,(3*$B1)]\n[(1 m),(mean($B1:$C1)),]\n";
+        let (book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        assert!(errors.is_empty());
+        let bytes = write_xlsx(&book).unwrap();
+        let (read, errors) = read(&bytes, Locale::canonical());
+        assert!(errors.is_empty(), "{:?}", errors);
+        //the equation with a unit is written as its value
+        assert_eq!(untranslatable(&book), 1);
+        assert_eq!(cell_texts(read.table()), cell_texts(book.table()));
+        assert_eq!(cell_texts(read.table())[1][..2], ["1 m", "mean($B1:$C1)"]);
+    }
+
+    #[test]
+    fn writes_circular_references_as_errors() {
+        let text = "#!tsheet 2\n[($a1),($a1 + 1)]\n";
+        let (book, _) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        for col in 0..2 {
+            let pos = Position { row: 0, col };
+            let data = book.table().get_value_at_position(&pos);
+            match cell_value(&book, 0, &pos, &data) {
+                Value::Text(t) => assert_eq!(t, "Err#9: Circular reference"),
+                Value::Number(n) => panic!("{} is {}", position_name(&pos), n),
+            }
+        }
+        assert!(write_xlsx(&book).is_ok());
+        assert!(crate::formats::ods::write(&book).is_ok());
+    }
+}
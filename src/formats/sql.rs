@@ -0,0 +1,186 @@
+use super::{
+    spreadsheet::{cell_value, Value},
+    Contents,
+};
+use crate::table::{base_10_to_col_num, Data, Position};
+use crate::workbook::Workbook;
+
+///The type of a database column, the narrowest type that holds every value in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        };
+    }
+}
+
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+///A value stored in a database, empty cells are NULL
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+///The largest whole number an f64 holds exactly
+const MAX_EXACT: f64 = 9007199254740992.0;
+
+///Quotes a table or column name
+pub fn quote_identifier(name: &str) -> String {
+    return format!("\"{}\"", name.replace('"', "\"\""));
+}
+
+fn quote_text(text: &str) -> String {
+    return format!("'{}'", text.replace('\'', "''"));
+}
+
+///The value of each cell in the sheet being edited, equations are evaluated unless their text is
+///wanted
+fn values(book: &Workbook, contents: Contents) -> Vec<Vec<Value>> {
+    let index = book.active_index();
+    let mut rows: Vec<Vec<Value>> = vec![];
+    for (row_no, row) in book.table().get_rows().iter().enumerate() {
+        let mut values: Vec<Value> = vec![];
+        for (col_no, data) in row.iter().enumerate() {
+            let pos = Position {
+                row: row_no,
+                col: col_no,
+            };
+            match data {
+                Data::Equation(e, ..) if contents == Contents::Formulas => {
+                    values.push(Value::Text(format!("={}", e)))
+                }
+                _ => values.push(cell_value(book, index, &pos, data)),
+            }
+        }
+        rows.push(values);
+    }
+    return rows;
+}
+
+///Names the columns by the header row, empty names become the column's letter and repeated names
+///are numbered
+fn column_names(header: &[Value]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for (col, value) in header.iter().enumerate() {
+        let name = match value {
+            Value::Number(n) => n.to_string(),
+            Value::Text(t) if t.trim().is_empty() => base_10_to_col_num(col + 1),
+            Value::Text(t) => t.trim().to_string(),
+        };
+        let mut unique = name.clone();
+        let mut n = 2;
+        while names.iter().any(|other| other.eq_ignore_ascii_case(&unique)) {
+            unique = format!("{}_{}", name, n);
+            n += 1;
+        }
+        names.push(unique);
+    }
+    return names;
+}
+
+///The columns of the sheet being edited, named by the header row, and the rows after the header
+pub fn columns_and_rows(book: &Workbook, contents: Contents) -> (Vec<Column>, Vec<Vec<Field>>) {
+    let values = values(book, contents);
+    let names = values.first().map_or(vec![], |header| column_names(header));
+    let body = values.get(1..).unwrap_or(&[]);
+    let mut columns: Vec<Column> = vec![];
+    for (col, name) in names.into_iter().enumerate() {
+        let mut kind: Option<ColumnType> = None;
+        for row in body {
+            let cell_kind = match &row[col] {
+                Value::Text(t) if t.is_empty() => continue,
+                Value::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_EXACT => ColumnType::Integer,
+                Value::Number(..) => ColumnType::Real,
+                Value::Text(..) => ColumnType::Text,
+            };
+            kind = match (kind, cell_kind) {
+                (Some(ColumnType::Text), _) | (_, ColumnType::Text) => Some(ColumnType::Text),
+                (Some(ColumnType::Real), _) | (_, ColumnType::Real) => Some(ColumnType::Real),
+                _ => Some(ColumnType::Integer),
+            };
+        }
+        columns.push(Column {
+            name,
+            kind: kind.unwrap_or(ColumnType::Text),
+        });
+    }
+    let rows = body
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(&columns)
+                .map(|(value, column)| match (value, column.kind) {
+                    (Value::Text(t), _) if t.is_empty() => Field::Null,
+                    (Value::Number(n), ColumnType::Integer) => Field::Integer(*n as i64),
+                    (Value::Number(n), ColumnType::Real) => Field::Real(*n),
+                    (Value::Number(n), ColumnType::Text) => Field::Text(n.to_string()),
+                    (Value::Text(t), _) => Field::Text(t.clone()),
+                })
+                .collect()
+        })
+        .collect();
+    return (columns, rows);
+}
+
+///The statement creating a table with columns
+pub fn create_table(name: &str, columns: &[Column]) -> String {
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|c| format!("  {} {}", quote_identifier(&c.name), c.kind.name()))
+        .collect();
+    return format!(
+        "CREATE TABLE {} (\n{}\n);\n",
+        quote_identifier(name),
+        columns.join(",\n")
+    );
+}
+
+///The start of a statement inserting a row into the table's columns, up to `VALUES`
+pub fn insert_into(name: &str, columns: &[Column]) -> String {
+    let names: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
+    return format!(
+        "INSERT INTO {} ({}) VALUES",
+        quote_identifier(name),
+        names.join(", ")
+    );
+}
+
+fn field_text(field: &Field) -> String {
+    return match field {
+        Field::Integer(n) => n.to_string(),
+        //SQL has no literal for infinity or NaN
+        Field::Real(n) if !n.is_finite() => "NULL".to_string(),
+        Field::Real(n) => n.to_string(),
+        Field::Text(t) => quote_text(t),
+        Field::Null => "NULL".to_string(),
+    };
+}
+
+///Writes a script creating a table called name, with a column for each cell of the header row and
+///a row for each row after it
+pub fn write(book: &Workbook, name: &str, contents: Contents) -> String {
+    let (columns, rows) = columns_and_rows(book, contents);
+    let mut text = create_table(name, &columns);
+    let insert = insert_into(name, &columns);
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(field_text).collect();
+        text += &format!("{} ({});\n", insert, fields.join(", "));
+    }
+    return text;
+}
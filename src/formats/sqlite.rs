@@ -0,0 +1,178 @@
+use rusqlite::{types::Value as SqlValue, Connection, OpenFlags};
+
+use super::{
+    sql::{self, Field},
+    Contents,
+};
+use crate::{
+    locale::Locale,
+    table::{Data, Table},
+    workbook::Workbook,
+};
+
+///Reads the table called name from the database at path, the column names become a frozen header
+///row. Errors that leave nothing to open are returned as Err
+pub fn read(path: &str, name: &str, locale: Locale) -> Result<(Table, Vec<String>), String> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Could not open {}: {}", path, e))?;
+    let query = format!("SELECT * FROM {}", sql::quote_identifier(name));
+    let mut statement = connection
+        .prepare(&query)
+        .map_err(|e| format!("Could not read {} from {}: {}", name, path, e))?;
+    let header: Vec<Data> = statement
+        .column_names()
+        .iter()
+        .map(|n| Data::String(n.to_string()))
+        .collect();
+    let cols = header.len();
+    let mut rows: Vec<Vec<Data>> = vec![header];
+    let mut errors: Vec<String> = vec![];
+    let mut results = statement.query([]).map_err(|e| e.to_string())?;
+    while let Some(result) = results.next().map_err(|e| e.to_string())? {
+        let mut cells: Vec<Data> = vec![];
+        for col in 0..cols {
+            let value: SqlValue = result.get(col).map_err(|e| e.to_string())?;
+            cells.push(match value {
+                SqlValue::Null => Data::String(String::new()),
+                SqlValue::Integer(n) => Data::Number(n.to_string()),
+                SqlValue::Real(n) => Data::Number(n.to_string()),
+                SqlValue::Text(t) => Data::String(t),
+                SqlValue::Blob(b) => {
+                    errors.push(format!(
+                        "Row {} column {}: {} bytes of binary data not read",
+                        rows.len(),
+                        col + 1,
+                        b.len()
+                    ));
+                    Data::String(String::new())
+                }
+            });
+        }
+        rows.push(cells);
+    }
+    let mut table = Table::from_rows(rows, locale);
+    table.get_settings_mut().frozen_rows = 1;
+    return Ok((table, errors));
+}
+
+///Writes the sheet being edited as the table called name in the database at path. A table with
+///that name is only replaced when replace is set, otherwise it is an error. Returns the number of
+///rows written
+pub fn write(
+    book: &Workbook,
+    path: &str,
+    name: &str,
+    contents: Contents,
+    replace: bool,
+) -> Result<usize, String> {
+    let (columns, rows) = sql::columns_and_rows(book, contents);
+    let mut connection = Connection::open(path).map_err(|e| e.to_string())?;
+    let transaction = connection.transaction().map_err(|e| e.to_string())?;
+    let exists = transaction
+        .query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1 COLLATE NOCASE",
+            [name],
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(|e| e.to_string())?
+        > 0;
+    if exists && !replace {
+        return Err(format!("table {} already exists, add replace to overwrite it", name));
+    }
+    let drop = format!("DROP TABLE IF EXISTS {}", sql::quote_identifier(name));
+    transaction.execute(&drop, []).map_err(|e| e.to_string())?;
+    transaction
+        .execute(&sql::create_table(name, &columns), [])
+        .map_err(|e| e.to_string())?;
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let insert = format!("{} ({})", sql::insert_into(name, &columns), placeholders);
+    {
+        let mut statement = transaction.prepare(&insert).map_err(|e| e.to_string())?;
+        for row in &rows {
+            let values = row.iter().map(|field| match field {
+                Field::Null => SqlValue::Null,
+                Field::Integer(n) => SqlValue::Integer(*n),
+                Field::Real(n) => SqlValue::Real(*n),
+                Field::Text(t) => SqlValue::Text(t.clone()),
+            });
+            statement
+                .execute(rusqlite::params_from_iter(values))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    transaction.commit().map_err(|e| e.to_string())?;
+    return Ok(rows.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::cell_texts;
+
+    ///A database file that is removed when the test ends
+    struct TempDb(String);
+
+    impl TempDb {
+        fn new(name: &str) -> TempDb {
+            let file = format!("sheet-{}-{}.db", std::process::id(), name);
+            let path = std::env::temp_dir().join(file);
+            let _ = std::fs::remove_file(&path);
+            return TempDb(path.display().to_string());
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn book(rows: &[&[&str]]) -> Workbook {
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|t| Data::String(t.to_string())).collect())
+            .collect();
+        return Workbook::new(Table::from_rows(rows, Locale::canonical()));
+    }
+
+    #[test]
+    fn writes_a_table_that_reads_back() {
+        let db = TempDb::new("round-trip");
+        let book = book(&[&["name", "count"], &["a", "1"], &["b", "2.5"]]);
+        assert_eq!(write(&book, &db.0, "items", Contents::Values, false), Ok(2));
+        let (table, errors) = read(&db.0, "items", Locale::canonical()).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(cell_texts(&table), cell_texts(book.table()));
+        assert_eq!(table.get_settings().frozen_rows, 1);
+    }
+
+    #[test]
+    fn only_replaces_a_table_when_asked() {
+        let db = TempDb::new("replace");
+        let first = book(&[&["name"], &["a"]]);
+        let second = book(&[&["name"], &["b"], &["c"]]);
+        assert!(write(&first, &db.0, "items", Contents::Values, false).is_ok());
+        assert!(write(&second, &db.0, "Items", Contents::Values, false).is_err());
+        let (table, _) = read(&db.0, "items", Locale::canonical()).unwrap();
+        assert_eq!(cell_texts(&table), cell_texts(first.table()));
+        assert_eq!(write(&second, &db.0, "items", Contents::Values, true), Ok(2));
+        let (table, _) = read(&db.0, "items", Locale::canonical()).unwrap();
+        assert_eq!(cell_texts(&table), cell_texts(second.table()));
+    }
+
+    #[test]
+    fn reads_nulls_and_reports_blobs() {
+        let db = TempDb::new("blobs");
+        let connection = Connection::open(&db.0).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE t (a INTEGER, b BLOB);\
+                 INSERT INTO t VALUES (1, x'0102'), (NULL, NULL);",
+            )
+            .unwrap();
+        let (table, errors) = read(&db.0, "t", Locale::canonical()).unwrap();
+        assert_eq!(cell_texts(&table), vec![vec!["a", "b"], vec!["1", ""], vec!["", ""]]);
+        assert_eq!(errors, vec!["Row 1 column 2: 2 bytes of binary data not read"]);
+        assert!(read(&db.0, "missing", Locale::canonical()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{settings::Include, sheet_tokenizer, workbook::Workbook};
+
+///A file loaded read only by `%include`
+#[derive(Debug)]
+pub struct IncludedSheet {
+    pub name: String,
+    pub path: PathBuf,
+    ///When the file was last changed, the file is loaded again when this changes
+    modified: Option<SystemTime>,
+    ///None if the file could not be loaded
+    pub book: Option<Workbook>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    return fs::metadata(path).and_then(|m| m.modified()).ok();
+}
+
+///Loads a sheet and the sheets it includes, chain is the files that led to this one and is used
+///to find cycles. Returns the problems found as messages
+fn load_file(path: &Path, chain: &[PathBuf]) -> (Option<Workbook>, Vec<String>) {
+    let canonical = match fs::canonicalize(path) {
+        Ok(p) => p,
+        Err(e) => return (None, vec![format!("Could not read {}: {}", path.display(), e)]),
+    };
+    if let Some(start) = chain.iter().position(|p| *p == canonical) {
+        let cycle: Vec<String> = chain[start..]
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        return (None, vec![format!("Include cycle: {}", cycle.join(" -> "))]);
+    }
+    let text = match fs::read_to_string(&canonical) {
+        Ok(t) => t,
+        Err(e) => return (None, vec![format!("Could not read {}: {}", path.display(), e)]),
+    };
+    let (mut book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(&text));
+    let mut messages: Vec<String> = errors
+        .iter()
+        .map(|e| format!("{}:{}", path.display(), e))
+        .collect();
+    let mut chain = chain.to_vec();
+    chain.push(canonical);
+    messages.append(&mut book.load_includes(chain));
+    book.recalculate();
+    return (Some(book), messages);
+}
+
+///Makes included match includes, loading files that were added or changed on disk since they were
+///loaded. chain ends with the file of the sheet that includes them
+pub fn sync_includes(
+    includes: &[Include],
+    included: &mut Vec<IncludedSheet>,
+    chain: &[PathBuf],
+) -> Vec<String> {
+    let dir = chain
+        .last()
+        .and_then(|p| p.parent())
+        .unwrap_or(Path::new("."));
+    let mut errors: Vec<String> = vec![];
+    let mut old = std::mem::take(included);
+    for include in includes {
+        let path = dir.join(&include.path);
+        let modified = modified_time(&path);
+        match old
+            .iter()
+            .position(|s| s.name == include.name && s.path == path)
+        {
+            Some(i) if old[i].modified == modified => {
+                let mut sheet = old.remove(i);
+                if let Some(book) = &mut sheet.book {
+                    errors.append(&mut book.reload_includes());
+                }
+                included.push(sheet);
+            }
+            _ => {
+                let (book, mut load_errors) = load_file(&path, chain);
+                errors.append(&mut load_errors);
+                included.push(IncludedSheet {
+                    name: include.name.clone(),
+                    path,
+                    modified,
+                    book,
+                });
+            }
+        }
+    }
+    return errors;
+}
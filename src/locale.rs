@@ -0,0 +1,191 @@
+///Separators used when reading numbers typed or imported by the user, and when displaying numbers.
+///Numbers in tsheet files always use the canonical form, eg: 1234.5
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locale {
+    pub decimal_separator: char,
+    pub thousands_separator: Option<char>,
+}
+
+impl Locale {
+    pub fn canonical() -> Locale {
+        Locale {
+            decimal_separator: '.',
+            thousands_separator: None,
+        }
+    }
+
+    ///Accepts a language such as `de`, or a posix locale such as `de_DE.UTF-8`
+    pub fn from_name(name: &str) -> Option<Locale> {
+        let language = name
+            .split(['_', '-', '.'])
+            .next()?
+            .to_lowercase();
+        let (decimal_separator, thousands_separator) = match language.as_str() {
+            "c" | "posix" | "canonical" => ('.', None),
+            "en" | "ja" | "ko" | "zh" | "th" | "he" => ('.', Some(',')),
+            "de" | "nl" | "es" | "it" | "pt" | "da" | "id" | "tr" | "el" | "ro" => {
+                (',', Some('.'))
+            }
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "nb" | "no" | "fi" | "uk" | "hu" => {
+                (',', Some(' '))
+            }
+            "ch" => ('.', Some('\'')),
+            _ => return None,
+        };
+        Some(Locale {
+            decimal_separator,
+            thousands_separator,
+        })
+    }
+
+    fn is_thousands_separator(&self, ch: char) -> bool {
+        match self.thousands_separator {
+            //no-break spaces are commonly used in place of spaces
+            Some(' ') => ch == ' ' || ch == '\u{a0}' || ch == '\u{202f}',
+            Some(sep) => ch == sep,
+            None => false,
+        }
+    }
+
+    ///Converts a number written in this locale to canonical text, returns None if text is not a
+    ///number. Thousands separators must separate groups of 3 digits
+    pub fn parse_number(&self, text: &str) -> Option<String> {
+        let text = text.trim();
+        if self.decimal_separator == '.' && self.thousands_separator.is_none() {
+            return text.parse::<f64>().ok().map(|_| text.to_string());
+        }
+        let (int_part, frac_part) = match text.find(self.decimal_separator) {
+            Some(i) => (
+                &text[0..i],
+                Some(&text[i + self.decimal_separator.len_utf8()..]),
+            ),
+            None => (text, None),
+        };
+        let groups: Vec<&str> = int_part.split(|c| self.is_thousands_separator(c)).collect();
+        if groups.len() > 1 {
+            let first = groups[0].trim_start_matches(['-', '+']);
+            if first.len() == 0
+                || first.len() > 3
+                || groups[1..].iter().any(|g| g.len() != 3)
+            {
+                return None;
+            }
+        }
+        let mut canonical = groups.concat();
+        if let Some(frac) = frac_part {
+            canonical += ".";
+            canonical += frac;
+        }
+        if canonical.contains(|c: char| c.is_whitespace() || c == ',') {
+            return None;
+        }
+        return canonical.parse::<f64>().ok().map(|_| canonical);
+    }
+
+    ///Formats canonical number text in this locale, text that is not a plain decimal number is
+    ///returned as is
+    pub fn format_number(&self, text: &str) -> String {
+        let (sign, unsigned) = match text.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", text),
+        };
+        let (int_part, frac_part) = match unsigned.split_once('.') {
+            Some((i, f)) => (i, Some(f)),
+            None => (unsigned, None),
+        };
+        if !int_part.chars().all(|c| c.is_ascii_digit())
+            || !frac_part.unwrap_or("").chars().all(|c| c.is_ascii_digit())
+        {
+            return text.to_string();
+        }
+        let mut grouped = String::new();
+        for (i, ch) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 {
+                if let Some(sep) = self.thousands_separator {
+                    grouped += &String::from(sep);
+                }
+            }
+            grouped += &String::from(ch);
+        }
+        return match frac_part {
+            Some(frac) => format!("{}{}{}{}", sign, grouped, self.decimal_separator, frac),
+            None => format!("{}{}", sign, grouped),
+        };
+    }
+
+    ///Canonical number text as it is edited in this locale, with this locale's decimal separator
+    ///but without thousands separators, so that typing or removing a digit does not break groups
+    pub fn edit_number(&self, text: &str) -> String {
+        return text.replace('.', &self.decimal_separator.to_string());
+    }
+
+    ///The separator used between fields in csv files written in this locale
+    pub fn csv_separator(&self) -> char {
+        if self.decimal_separator == ',' {
+            return ';';
+        }
+        return ',';
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Locale;
+
+    fn locale(name: &str) -> Locale {
+        return Locale::from_name(name).unwrap();
+    }
+
+    #[test]
+    fn parses_numbers_in_each_locale() {
+        let cases = [
+            ("en_US.UTF-8", "-1,234,567.5", Some("-1234567.5")),
+            ("de", "1.234,5", Some("1234.5")),
+            ("de", "1234,5", Some("1234.5")),
+            ("fr", "1 234,5", Some("1234.5")),
+            ("fr", "1\u{a0}234", Some("1234")),
+            ("ch", "1'234.5", Some("1234.5")),
+            ("c", " 1e3 ", Some("1e3")),
+            ("en", "12,34", None),
+            ("en", "1234,567", None),
+            ("de", "1,234.5", None),
+            ("fr", "1 23", None),
+            ("en", "abc", None),
+        ];
+        for (name, text, expected) in cases {
+            let parsed = locale(name).parse_number(text);
+            assert_eq!(parsed.as_deref(), expected, "{} {}", name, text);
+        }
+    }
+
+    #[test]
+    fn formats_numbers_in_each_locale() {
+        assert_eq!(locale("en").format_number("-1234567.25"), "-1,234,567.25");
+        assert_eq!(locale("de").format_number("1234.5"), "1.234,5");
+        assert_eq!(locale("fr").format_number("1234"), "1 234");
+        assert_eq!(locale("ch").format_number("123"), "123");
+        assert_eq!(Locale::canonical().format_number("1234.5"), "1234.5");
+        assert_eq!(locale("de").format_number("1e10"), "1e10");
+        assert_eq!(locale("de").format_number("NaN"), "NaN");
+    }
+
+    #[test]
+    fn formatted_numbers_parse_back() {
+        for name in ["en", "de", "fr", "ch", "c"] {
+            for text in ["0", "-12", "1234", "1234567.125", "999.5"] {
+                let formatted = locale(name).format_number(text);
+                let parsed = locale(name).parse_number(&formatted);
+                assert_eq!(parsed.as_deref(), Some(text), "{} {}", name, formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_locale_names() {
+        assert_eq!(locale("de_DE.UTF-8"), locale("de"));
+        assert_eq!(locale("POSIX"), Locale::canonical());
+        assert_eq!(Locale::from_name("xx"), None);
+        assert_eq!(locale("de").csv_separator(), ';');
+        assert_eq!(locale("en").csv_separator(), ',');
+    }
+}
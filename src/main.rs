@@ -1,55 +1,75 @@
 mod calculator;
+mod decimal;
+mod formats;
+mod include;
+mod locale;
 mod position_parser;
 mod program;
+mod settings;
 mod sheet_tokenizer;
 mod table;
+mod text_view;
 mod command_line;
 mod undo_tree;
+mod workbook;
 
 use command_line::CommandLine;
+use formats::{Contents, Format, WriteOptions};
+use locale::Locale;
 use program::Program;
 use std::os::unix::io::AsRawFd;
 use table::{Direction, Position, Table};
+use workbook::Workbook;
 
 use std::{
+    collections::HashMap,
     env::Args,
-    io::BufRead
+    io::{Read, Write},
 };
 
 use base64::{engine, prelude::*};
 
 use termios::{tcsetattr, Termios, ECHO, ICANON, TCSANOW};
 
+///Options that are given without a value
+const FLAGS: [&str; 1] = ["--write"];
+
 struct ProgramArguments {
     opts: std::collections::HashMap<String, String>,
+    ///The value of every --set, in the order given
+    sets: Vec<String>,
     file: String,
 }
 
 fn parse_args(args: &mut Args) -> ProgramArguments {
     let _prog_name = args.next(); //skip prog_name
     let mut opts: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    let mut sets: Vec<String> = vec![];
     let mut file_name: Option<String> = None;
     let mut parsing_opts = true;
+    //options may also follow the file, as in `sheet data.tsheet --get B3`
     while let Some(s) = args.next() {
         if s == "--" {
             parsing_opts = false;
+        } else if parsing_opts && FLAGS.contains(&s.as_str()) {
+            opts.insert(s, String::new());
         } else if parsing_opts && s.starts_with("-") {
             match args.next() {
-                Some(v) => opts.insert(s, v),
+                Some(v) if s == "--set" => sets.push(v),
+                Some(v) => {
+                    opts.insert(s, v);
+                }
                 None => break,
             };
-        } else {
+        } else if file_name.is_none() {
             file_name = Some(s);
-            break;
         }
     }
-    match file_name {
-        None => ProgramArguments {
-            opts,
-            file: "-".to_string(),
-        },
-        Some(file) => ProgramArguments { opts, file },
-    }
+    return ProgramArguments {
+        opts,
+        sets,
+        file: file_name.unwrap_or("-".to_string()),
+    };
 }
 
 fn execute_command(program: &mut program::Program, command: &str) {
@@ -62,25 +82,237 @@ fn execute_command(program: &mut program::Program, command: &str) {
                 std::process::exit(0);
             }
             if c == "w" {
-                let file_name = args.next();
-                let path = match file_name{
-                    None => program.get_file_path(),
-                    Some(name) => name
+                let path = match args.next() {
+                    None => program.get_file_path().to_string(),
+                    Some(name) => name.to_string(),
+                };
+                let options = WriteOptions::new(program.csv_options);
+                save(program, &path, None, options);
+            }
+            if c == "export" {
+                let format_name = args.next().unwrap_or("");
+                let mut options = WriteOptions::new(program.csv_options);
+                //the path, then the name of the table for sql and sqlite
+                let mut names: Vec<String> = vec![];
+                let mut replace = false;
+                for arg in args.by_ref() {
+                    match arg {
+                        "values" => options.contents = Contents::Values,
+                        "formulas" => options.contents = Contents::Formulas,
+                        "classes" => options.html_classes = true,
+                        "replace" => replace = true,
+                        _ => names.push(arg.to_string()),
+                    }
+                }
+                let format = Format::from_name(format_name);
+                let extension = match format {
+                    Some(f) => f.extension(),
+                    None => "db",
                 };
-                let sheet = program.table.to_sheet();
-                std::fs::write(path, sheet).unwrap();
-                program.command_line.print("Saved");
+                //next to the file being edited unless a path is given
+                let path = names.first().cloned().unwrap_or_else(|| {
+                    std::path::Path::new(program.get_file_path())
+                        .with_extension(extension)
+                        .display()
+                        .to_string()
+                });
+                //tables are named after the file they are written to unless a name is given
+                let table_name = names.get(1).cloned().unwrap_or_else(|| {
+                    std::path::Path::new(&path)
+                        .file_stem()
+                        .map_or("sheet".to_string(), |s| s.to_string_lossy().to_string())
+                });
+                match format {
+                    Some(format) => {
+                        options.table_name = Some(table_name);
+                        save(program, &path, Some(format), options);
+                    }
+                    None if format_name == "sqlite" => {
+                        let written = formats::sqlite::write(
+                            program.book,
+                            &path,
+                            &table_name,
+                            options.contents,
+                            replace,
+                        );
+                        match written {
+                            Ok(rows) => program.command_line.print(&format!(
+                                "Exported {} rows to {} as {}",
+                                rows, path, table_name
+                            )),
+                            Err(e) => program
+                                .command_line
+                                .print(&format!("Could not export to {}: {}", path, e)),
+                        }
+                    }
+                    _ => program.command_line.print(
+                        "Usage: export <format> [values|formulas] [classes] [replace] [path] [table]",
+                    ),
+                }
+            }
+            if c == "fmt" {
+                match args.next() {
+                    Some("onsave") => {
+                        program.book.table_mut().get_settings_mut().format_on_save = true;
+                        program.command_line.print("Formatting equations on save");
+                    }
+                    Some("noonsave") => {
+                        program.book.table_mut().get_settings_mut().format_on_save = false;
+                        program.command_line.print("Not formatting equations on save");
+                    }
+                    _ => {
+                        program.book.table_mut().format_equations();
+                        program.save_state();
+                        program.command_line.print("Formatted equations");
+                    }
+                }
+            }
+            if c == "explain" {
+                let pos = program.book.table().get_pos();
+                let lines = program.book.explain(&pos);
+                program.text_view.set_text("explain (q to close)", lines);
+                program.set_mode(program::Mode::View);
+            }
+            if c == "recalc" {
+                program.book.recalc_volatile();
+                program.command_line.print("Recalculated");
+            }
+            if c == "seed" {
+                let seed = args.next().and_then(|s| s.parse::<u64>().ok());
+                program.book.table_mut().set_seed(seed);
+                match seed {
+                    None => program.command_line.print("Seed removed"),
+                    Some(s) => program.command_line.print(&format!("Seed set to {}", s)),
+                }
+            }
+            if c == "locale" {
+                match args.next().map(Locale::from_name) {
+                    Some(Some(locale)) => {
+                        program.book.set_locale(locale);
+                        program.command_line.print("Locale changed");
+                    }
+                    _ => program.command_line.print("Unknown locale"),
+                }
+            }
+            if c == "decimal" {
+                let decimal = settings::parse_decimal_args(args.by_ref());
+                program.book.table_mut().get_settings_mut().decimal = decimal;
+                match decimal {
+                    None => program.command_line.print("Decimal mode off"),
+                    Some(d) => program.command_line.print(&format!(
+                        "Decimal mode on, rounding {}",
+                        d.rounding.name()
+                    )),
+                }
+            }
+            if c == "iterate" {
+                match settings::parse_iteration_args(args.by_ref()) {
+                    Err(e) => program
+                        .command_line
+                        .print(&format!("Invalid arguments for iterate, {}", e)),
+                    Ok(iteration) => {
+                        program.book.table_mut().get_settings_mut().iteration = iteration;
+                        match iteration {
+                            None => program.command_line.print("Iterative calculation off"),
+                            Some(i) => program.command_line.print(&format!(
+                                "Iterating up to {} times, epsilon {}",
+                                i.max_iterations, i.epsilon
+                            )),
+                        }
+                    }
+                }
+            }
+            if c == "sheet" {
+                let name = command["sheet".len()..].trim();
+                //a sheet is only added when asked for, so a mistyped name is not saved as a sheet
+                let new_name = name.strip_prefix("new ").map(|n| n.trim());
+                if name.len() == 0 {
+                    let current = program.book.active_index();
+                    program.command_line.print(&program.book.sheet_name(current));
+                } else if let Some(new_name) = new_name {
+                    if program.book.find_index(new_name).is_some() {
+                        program
+                            .command_line
+                            .print(&format!("There is already a sheet called {}", new_name));
+                    } else {
+                        let index = program.book.add_sheet(new_name);
+                        program.switch_sheet(index);
+                        program.command_line.print(&format!("Added sheet {}", new_name));
+                    }
+                } else if let Some(index) = program.book.find_index(name) {
+                    program.switch_sheet(index);
+                } else {
+                    program.command_line.print(&format!(
+                        "No sheet called {}, use sheet new {} to add it",
+                        name, name
+                    ));
+                }
+            }
+            //these are saved as directives, so they take the same arguments
+            if c == "name" || c == "freeze" || c == "range" || c == "format" || c == "include" {
+                if program.book.table_mut().get_settings_mut().apply_directive(command) {
+                    program.command_line.print("Updated");
+                } else {
+                    program.command_line.print(&format!("Invalid arguments for {}", c));
+                }
             }
         }
     }
 }
 
+///Saves the sheet to path, in format if given, otherwise in the format of path's extension.
+///Paths without a known extension are saved in the format the file was opened in
+fn save(program: &mut program::Program, path: &str, format: Option<Format>, options: WriteOptions) {
+    let same_file = path == program.get_file_path();
+    let format = match format {
+        Some(f) => f,
+        None if same_file => program.file_format,
+        None => Format::from_path(path).unwrap_or(program.file_format),
+    };
+    let bytes = match formats::write(program.book, format, &options) {
+        Ok(b) => b,
+        Err(e) => {
+            program.command_line.print(&format!("Could not save {}: {}", path, e));
+            return;
+        }
+    };
+    if let Err(e) = std::fs::write(path, bytes) {
+        program.command_line.print(&format!("Could not save {}: {}", path, e));
+        return;
+    }
+    //say what was not saved, so saving never quietly loses anything
+    let mut lost: Vec<String> = vec![];
+    if format.is_spreadsheet() {
+        match formats::spreadsheet::untranslatable(program.book) {
+            0 => {}
+            1 => lost.push("1 equation saved as its value".to_string()),
+            n => lost.push(format!("{} equations saved as values", n)),
+        }
+    } else if !format.keeps_equations()
+        && options.contents == Contents::Values
+        && program.book.table().has_equations()
+    {
+        lost.push("equations saved as values".to_string());
+    }
+    if !format.keeps_sheets() && program.book.len() > 1 {
+        lost.push("only this sheet saved".to_string());
+    }
+    match lost.len() {
+        0 => program.command_line.print(&format!("Saved as {}", format.name())),
+        _ => program.command_line.print(&format!(
+            "Saved as {}, {}",
+            format.name(),
+            lost.join(", ")
+        )),
+    }
+}
+
 fn handle_command_mode(program: &mut program::Program, key: program::KeySequence) {
     if key.action as u8 == 10 {
         let text = program.command_line.get_current_text().to_owned();
         program.command_line.clear_text();
-        execute_command(program, &text);
         program.set_mode(program::Mode::Normal);
+        execute_command(program, &text);
     } else if key.action as u8 == 127 {
         program.command_line.remove_last_char();
     } else {
@@ -89,7 +321,7 @@ fn handle_command_mode(program: &mut program::Program, key: program::KeySequence
 }
 
 fn get_range_from_motion(program: &Program, motion: &str) -> (Position, Position) {
-    let pos = program.table.get_pos();
+    let pos = program.book.table().get_pos();
     return match motion {
         "l" | "h" => (
             Position {
@@ -98,7 +330,7 @@ fn get_range_from_motion(program: &Program, motion: &str) -> (Position, Position
             },
             Position {
                 row: pos.row,
-                col: program.table.get_size()[1] - 1,
+                col: program.book.table().get_size()[1] - 1,
             },
         ),
         "j" | "k" => (
@@ -107,7 +339,7 @@ fn get_range_from_motion(program: &Program, motion: &str) -> (Position, Position
                 col: pos.col,
             },
             Position {
-                row: program.table.get_size()[0] - 1,
+                row: program.book.table().get_size()[0] - 1,
                 col: 0,
             },
         ),
@@ -117,7 +349,7 @@ fn get_range_from_motion(program: &Program, motion: &str) -> (Position, Position
                 col: pos.col,
             },
             Position {
-                row: program.table.get_size()[0] - 1,
+                row: program.book.table().get_size()[0] - 1,
                 col: pos.col,
             },
         ),
@@ -138,7 +370,7 @@ fn get_range_from_motion(program: &Program, motion: &str) -> (Position, Position
             },
             Position {
                 row: pos.row,
-                col: program.table.get_size()[1] - 1,
+                col: program.book.table().get_size()[1] - 1,
             },
         ),
         "0" => (
@@ -169,15 +401,15 @@ fn handle_normal_mode(program: &mut program::Program, key: program::KeySequence)
             program.set_mode(program::Mode::Command);
         }
         ">" => {
-            let pos = program.table.get_pos();
-            let cur_size = program.table.get_col_width(pos.col).unwrap();
-            program.table.resize_col(pos.col, cur_size + 1);
+            let pos = program.book.table().get_pos();
+            let cur_size = program.book.table().get_col_width(pos.col).unwrap();
+            program.book.table_mut().resize_col(pos.col, cur_size + 1);
         }
         "<" => {
-            let pos = program.table.get_pos();
-            let cur_size = program.table.get_col_width(pos.col).unwrap();
+            let pos = program.book.table().get_pos();
+            let cur_size = program.book.table().get_col_width(pos.col).unwrap();
             if cur_size > 0 {
-                program.table.resize_col(pos.col, cur_size - 1);
+                program.book.table_mut().resize_col(pos.col, cur_size - 1);
             }
         }
         "y" => {
@@ -185,7 +417,7 @@ fn handle_normal_mode(program: &mut program::Program, key: program::KeySequence)
             let direction = program.get_key(&mut reader);
             let mut s: String = String::new();
             let range = get_range_from_motion(program, &direction.action.to_string());
-            let data = program.table.get_values_at_range(&range.0, &range.1);
+            let data = program.book.table().get_values_at_range(&range.0, &range.1);
             for d in data {
                 let temp = match d {
                     table::Data::Number(n)
@@ -198,116 +430,145 @@ fn handle_normal_mode(program: &mut program::Program, key: program::KeySequence)
             print!("\x1b]52;c;{}\x07", encoded)
         }
         "s" => {
-            program.table.clear_cell(&program.table.get_pos());
+            let pos = program.book.table().get_pos();
+            program.book.table_mut().clear_cell(&pos);
             program.set_mode(program::Mode::Insert);
         }
         "\x1b[B" | "j" => {
             for _ in 0..key.count {
-                if program.table.cursor_at_bottom() {
-                    program.table.add_row(program.table.get_pos().row + 1);
+                if program.book.table().cursor_at_bottom() {
+                    let row = program.book.table().get_pos().row;
+                    program.book.table_mut().add_row(row + 1);
                 }
-                program.table.move_cursor(Direction::Down);
+                program.book.table_mut().move_cursor(Direction::Down);
             }
         }
         "\x1b[A" | "k" => {
             for _ in 0..key.count {
-                program.table.move_cursor(Direction::Up);
+                program.book.table_mut().move_cursor(Direction::Up);
             }
         }
         "0" | "^" => {
-            program.table.move_cursor(Direction::MostLeft)
+            program.book.table_mut().move_cursor(Direction::MostLeft)
         },
         "$" => {
-            program.table.move_cursor(Direction::MostRight)
+            program.book.table_mut().move_cursor(Direction::MostRight)
         }
         "\x1b[C" | "l" => {
             for _ in 0..key.count {
-                if program.table.cursor_at_right() {
-                    program.table.add_col(program.table.get_pos().col + 1);
+                if program.book.table().cursor_at_right() {
+                    let col = program.book.table().get_pos().col;
+                    program.book.table_mut().add_col(col + 1);
                 }
-                program.table.move_cursor(Direction::Right);
+                program.book.table_mut().move_cursor(Direction::Right);
             }
         }
-        "L" => program.table.move_cursor(Direction::MostRight),
-        "H" => program.table.move_cursor(Direction::MostLeft),
+        "L" => program.book.table_mut().move_cursor(Direction::MostRight),
+        "H" => program.book.table_mut().move_cursor(Direction::MostLeft),
         "\x1b[D" | "h" => {
             for _ in 0..key.count {
-                program.table.move_cursor(Direction::Left);
+                program.book.table_mut().move_cursor(Direction::Left);
+            }
+        }
+        "g" => {
+            let mut reader = std::io::stdin();
+            let next = program.get_key(&mut reader);
+            let count = program.book.len();
+            let current = program.book.active_index();
+            match next.key.as_str() {
+                "t" => program.switch_sheet((current + 1) % count),
+                "T" => program.switch_sheet((current + count - 1) % count),
+                //g on its own goes to the top, the key after it is handled as usual
+                _ => {
+                    program.book.table_mut().move_cursor(Direction::Top);
+                    handle_normal_mode(program, next);
+                }
             }
         }
-        "g" | "K" => program.table.move_cursor(Direction::Top),
-        "G" | "J" => program.table.move_cursor(Direction::Bottom),
+        "K" => program.book.table_mut().move_cursor(Direction::Top),
+        "G" | "J" => program.book.table_mut().move_cursor(Direction::Bottom),
         "R" => {
-            let pos = program.table.get_pos();
+            let pos = program.book.table().get_pos();
             for _ in 0..key.count {
-                program.table.add_row(pos.row);
+                program.book.table_mut().add_row(pos.row);
             }
         }
         "r" => {
-            let pos = program.table.get_pos();
+            let pos = program.book.table().get_pos();
             for _ in 0..key.count {
-                program.table.add_row(pos.row + 1);
-                program.table.move_cursor(Direction::Down);
+                program.book.table_mut().add_row(pos.row + 1);
+                program.book.table_mut().move_cursor(Direction::Down);
             }
         }
         "c" => {
-            let pos = program.table.get_pos();
+            let pos = program.book.table().get_pos();
             for _ in 0..key.count {
-                program.table.add_col(pos.col + 1);
-                program.table.move_cursor(Direction::Right)
+                program.book.table_mut().add_col(pos.col + 1);
+                program.book.table_mut().move_cursor(Direction::Right)
             }
         }
         "C" => {
-            let pos = program.table.get_pos();
+            let pos = program.book.table().get_pos();
             for _ in 0..key.count {
-                program.table.add_col(pos.col);
+                program.book.table_mut().add_col(pos.col);
             }
         }
         "d" => {
-            let mut row = program.table.get_pos().row;
-            let count = if key.count >= program.table.get_size()[0] {
-                program.table.get_size()[0]
+            let mut row = program.book.table().get_pos().row;
+            let count = if key.count >= program.book.table().get_size()[0] {
+                program.book.table().get_size()[0]
             } else {
                 key.count
             };
             for _ in 0..count {
-                if row >= program.table.get_size()[0] {
+                if row >= program.book.table().get_size()[0] {
                     row -= 1;
                 }
-                program.table.remove_row(row);
+                program.book.table_mut().remove_row(row);
             }
         }
         "D" => {
-            let mut col = program.table.get_pos().col;
-            let count = if key.count >= program.table.get_size()[1] {
-                program.table.get_size()[1]
+            let mut col = program.book.table().get_pos().col;
+            let count = if key.count >= program.book.table().get_size()[1] {
+                program.book.table().get_size()[1]
             } else {
                 key.count
             };
             for _ in 0..count {
-                if col >= program.table.get_size()[1] {
+                if col >= program.book.table().get_size()[1] {
                     col -= 1;
                 }
-                program.table.remove_col(col);
+                program.book.table_mut().remove_col(col);
             }
         }
         "S" => program.save_state(),
         "w" => {
-            let sheet = program.table.to_sheet();
-            std::fs::write(program.get_file_path(), sheet).unwrap();
+            let path = program.get_file_path().to_string();
+            let options = WriteOptions::new(program.csv_options);
+            save(program, &path, None, options);
             program.save_state();
-            program.command_line.print("Saved")
         }
         "x" => {
-            let pos = program.table.get_pos();
-            program.table.clear_cell(&pos)
+            let pos = program.book.table().get_pos();
+            program.book.table_mut().clear_cell(&pos)
         }
         _ => {}
     }
 }
 
+fn handle_view_mode(program: &mut program::Program, key: program::KeySequence) {
+    match key.key.as_str() {
+        "q" | "\x1b" => program.set_mode(program::Mode::Normal),
+        "\x1b[B" | "j" => program.text_view.scroll_down(key.count),
+        "\x1b[A" | "k" => program.text_view.scroll_up(key.count),
+        "g" => program.text_view.scroll_to_top(),
+        "G" => program.text_view.scroll_to_bottom(),
+        _ => {}
+    }
+}
+
 fn handle_insert_mode(program: &mut program::Program, key: program::KeySequence) {
-    let table = &mut program.table;
+    let table = program.book.table_mut();
     match key.action as u8 {
         //backspace
         127 => table.remove_last_char_in_cell(&table.get_pos()),
@@ -318,7 +579,7 @@ fn handle_insert_mode(program: &mut program::Program, key: program::KeySequence)
         b'\t' => table.move_cursor(Direction::Right),
         b'=' => {
             if table.cursor_pos_is_empty() {
-                table.convert_cell(&table.get_pos(), table::Data::Equation(String::new(), None))
+                table.convert_cell(&table.get_pos(), table::Data::equation(String::new(), None))
             } else {
                 table.append_text_to_cell(&table.get_pos(), key.key);
             }
@@ -332,6 +593,7 @@ fn handle_mode(program: &mut program::Program, key: program::KeySequence) {
         program::Mode::Normal => handle_normal_mode(program, key),
         program::Mode::Insert => handle_insert_mode(program, key),
         program::Mode::Command => handle_command_mode(program, key),
+        program::Mode::View => handle_view_mode(program, key),
     }
 }
 
@@ -346,6 +608,153 @@ fn setup_terminal() -> termios::Termios {
     return termios;
 }
 
+///Finds the format called name, exits if there is none
+fn format_or_exit(name: &str) -> Format {
+    return Format::from_name(name).unwrap_or_else(|| {
+        eprintln!("Unknown file type: {}", name);
+        std::process::exit(1);
+    });
+}
+
+///Writes bytes to stdout, exits if they cannot be written, as when the reader of a pipe has
+///closed it
+fn write_stdout_or_exit(bytes: &[u8]) {
+    let mut stdout = std::io::stdout();
+    if let Err(e) = stdout.write_all(bytes).and_then(|_| stdout.flush()) {
+        //a pipe closed early, as by `head`, is not worth a message
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            eprintln!("Could not write to stdout: {}", e);
+        }
+        std::process::exit(1);
+    }
+}
+
+///Writes the sheet in format to the path given by `-o`, or to stdout, without opening the editor,
+///then exits. The exit code is 1 if there were problems loading the file
+fn export(
+    book: &mut Workbook,
+    format: Format,
+    opts: &HashMap<String, String>,
+    csv_options: formats::csv::CsvOptions,
+    load_errors: &[String],
+) {
+    let mut options = WriteOptions::new(csv_options);
+    if let Some(contents) = opts.get("--contents") {
+        options.contents = Contents::from_name(contents).unwrap_or_else(|| {
+            eprintln!("--contents must be values or formulas, not {}", contents);
+            std::process::exit(1);
+        });
+    }
+    options.html_classes = opts.get("--html-classes").map(|c| c.as_str()) == Some("yes");
+    options.table_name = opts.get("--table").cloned();
+    for error in load_errors {
+        eprintln!("{}", error);
+    }
+    book.recalculate();
+    let bytes = formats::write(book, format, &options).unwrap_or_else(|e| {
+        eprintln!("Could not export: {}", e);
+        std::process::exit(1);
+    });
+    match opts.get("-o") {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, bytes) {
+                eprintln!("Could not write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => write_stdout_or_exit(&bytes),
+    }
+    std::process::exit(if load_errors.len() > 0 { 1 } else { 0 });
+}
+
+///Parses a cell such as B3 or $B3 given on the command line, exits if it is not in the sheet
+fn cell_or_exit(table: &Table, name: &str) -> Position {
+    let [rows, cols] = table.get_size();
+    match table::parse_position_name(name.trim()) {
+        Some(pos) if pos.row < rows && pos.col < cols => return pos,
+        _ => {
+            eprintln!("{} is not a cell in the sheet", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+///Sets the cells given by --set, prints the values asked for by --get and --eval, and saves the
+///file if --write is given, without opening the editor. Then exits, the exit code is 1 if there
+///were problems loading the file or a value could not be evaluated
+fn query(
+    program_args: &ProgramArguments,
+    fp: &str,
+    file_format: Format,
+    book: &mut Workbook,
+    csv_options: formats::csv::CsvOptions,
+    load_errors: &[String],
+) {
+    let opts = &program_args.opts;
+    let mut failed = load_errors.len() > 0;
+    for error in load_errors {
+        eprintln!("{}", error);
+    }
+    for set in &program_args.sets {
+        let parsed = set
+            .split_once('=')
+            .and_then(|(cell, text)| Some((table::parse_position_name(cell.trim())?, text)));
+        match parsed {
+            Some((pos, text)) => {
+                if !book.table_mut().set_text_at_position(&pos, text) {
+                    eprintln!(
+                        "{} is more than {} rows or columns outside the sheet",
+                        table::position_name(&pos),
+                        table::MAX_GROWTH
+                    );
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                eprintln!("--set must be a cell and a value, as in A2=42, not {}", set);
+                std::process::exit(1);
+            }
+        }
+    }
+    book.recalculate();
+    if let Some(cell) = opts.get("--get") {
+        let pos = cell_or_exit(book.table(), cell);
+        let data = book.table().get_value_at_position(&pos);
+        if let Some(Err(..)) = data.evaluate(book, &pos) {
+            failed = true;
+        }
+        write_stdout_or_exit(format!("{}\n", data.value_text(book, &pos)).as_bytes());
+    }
+    if let Some(equation) = opts.get("--eval") {
+        let mut symbols = calculator::cell_symbols(&book.table().get_pos());
+        match calculator::calculate(equation, &mut symbols, book) {
+            Ok(v) => {
+                write_stdout_or_exit(format!("{}\n", book.table().format_result(&v)).as_bytes())
+            }
+            Err(e) => {
+                eprintln!("{}", table::error_text(&e));
+                failed = true;
+            }
+        }
+    }
+    if opts.contains_key("--write") {
+        if fp == "-" {
+            eprintln!("--write needs a file to save to");
+            std::process::exit(1);
+        }
+        let written = formats::write(book, file_format, &WriteOptions::new(csv_options))
+            .and_then(|bytes| std::fs::write(fp, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("Could not save {}: {}", fp, e);
+            std::process::exit(1);
+        }
+        if !file_format.keeps_equations() && book.table().has_equations() {
+            eprintln!("Saved as {}, equations saved as values", file_format.name());
+        }
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
+
 fn main() {
     // let mut lexer = calculator::Lexer::new("sum($a1:$b1)/2");
     // let toks = lexer.tokenize();
@@ -388,22 +797,94 @@ fn main() {
 
     let stdin = std::io::stdin();
 
-    let fp = program_args.file;
-    let default_ft = "tsheet".to_string();
-    let file_type = program_args.opts.get("-f").unwrap_or(&default_ft);
+    let querying = ["--get", "--eval", "--write"]
+        .iter()
+        .any(|o| program_args.opts.contains_key(*o))
+        || program_args.sets.len() > 0;
+    let headless = querying
+        || ["--convert", "--export"]
+            .iter()
+            .any(|o| program_args.opts.contains_key(*o));
+    //--convert names the file to read instead of the editor's file
+    let fp = match program_args.opts.get("--convert") {
+        Some(path) => path.clone(),
+        None => program_args.file.clone(),
+    };
+    //the format is given by -f, or by the file's extension
+    let file_format = match program_args.opts.get("-f") {
+        None => Format::from_path(&fp).unwrap_or(Format::Tsheet),
+        Some(name) => format_or_exit(name),
+    };
+    let locale = match program_args.opts.get("-l") {
+        None => Locale::canonical(),
+        Some(name) => Locale::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown locale: {}", name);
+            std::process::exit(1);
+        }),
+    };
 
-    let mut text = String::new();
-    if fp == "-" {
-        let mut temp = String::new();
-        for line in stdin.lock().lines() {
-            temp += &line.unwrap();
-        }
-        text = temp;
-    } else {
-        let data = std::fs::read_to_string(&fp);
-        if let Ok(t) = data {
-            text = t;
+    let read_options = formats::ReadOptions::from_args(&program_args.opts, &locale)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let csv_options = read_options.csv;
+
+    let (mut book, mut load_errors) = match program_args.opts.get("--import") {
+        Some(source) => formats::import(source, locale).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => {
+            //read as bytes as csv files may not be utf-8
+            let mut bytes: Vec<u8> = vec![];
+            //without the editor a sheet is only read from stdin when something is piped in
+            let stdin_is_tty = unsafe { libc::isatty(0) } == 1;
+            if fp == "-" && !(headless && stdin_is_tty) {
+                stdin.lock().read_to_end(&mut bytes).unwrap();
+            } else {
+                match std::fs::read(&fp) {
+                    Ok(b) => bytes = b,
+                    //the editor opens a missing file as a new sheet, without the editor there
+                    //is nothing to do with it
+                    Err(e) if headless => {
+                        eprintln!("Could not read {}: {}", fp, e);
+                        std::process::exit(1);
+                    }
+                    Err(_) => {}
+                }
+            }
+            //parse data depending on file type
+            formats::read(&bytes, file_format, &read_options, locale)
         }
+    };
+    //included files are found relative to this file
+    let include_chain = match std::fs::canonicalize(&fp) {
+        Ok(path) if fp != "-" => vec![path],
+        _ => vec![],
+    };
+    load_errors.append(&mut book.load_includes(include_chain));
+
+    if querying {
+        query(&program_args, &fp, file_format, &mut book, csv_options, &load_errors);
+    }
+    if let Some(name) = program_args.opts.get("--export") {
+        let format = format_or_exit(name);
+        export(&mut book, format, &program_args.opts, csv_options, &load_errors);
+    }
+    if program_args.opts.contains_key("--convert") {
+        //the format is given by --to, or by the extension of the output
+        let format = match program_args.opts.get("--to") {
+            Some(name) => format_or_exit(name),
+            None => match program_args.opts.get("-o").and_then(|o| Format::from_path(o)) {
+                Some(f) => f,
+                None => {
+                    eprintln!("--convert needs --to <format>, or -o with a known extension");
+                    std::process::exit(1);
+                }
+            },
+        };
+        export(&mut book, format, &program_args.opts, csv_options, &load_errors);
     }
 
     //hack to close pipe on stdin
@@ -415,23 +896,35 @@ fn main() {
 
     let old_termios = setup_terminal();
 
-    //parse data depending on file type
-    let mut table: Table = if file_type == "csv" {
-        Table::from_csv(&text, ',')
-    } else {
-        let toks = sheet_tokenizer::parse(text.as_str());
-        Table::from_sheet_tokens(toks)
-    };
-
     let mut command_line: CommandLine = CommandLine::new(0, 30);
 
-    let mut program = program::Program::new(&fp, &mut table, &mut command_line);
+    let mut program = program::Program::new(&fp, &mut book, &mut command_line);
+    program.file_format = file_format;
+    program.csv_options = csv_options;
+
+    if load_errors.len() > 0 {
+        let title = format!(
+            "{} problems loading {} (q to close)",
+            load_errors.len(),
+            fp
+        );
+        program.text_view.set_text(&title, load_errors);
+        program.set_mode(program::Mode::View);
+    }
 
     let mut reader = stdin;
     while program.running {
+        if let Some(error) = program.book.reload_includes().first() {
+            program.command_line.print(error);
+        }
+        program.book.recalculate();
         print!("\x1b[2J\x1b[0H");
         //TODO: move the actual cursor to the selected row
-        println!("{}", program.table.display(10, &program));
+        if program.is_mode(program::Mode::View) {
+            println!("{}", program.text_view.display(program.term_info.lines));
+        } else {
+            println!("{}", program.book.display(10, &program));
+        }
         println!("{}", program.command_line.display());
         let key_sequence = program.get_key(&mut reader);
         handle_mode(&mut program, key_sequence);
@@ -2,14 +2,18 @@ use std::io::{Read, Stdin};
 use std::rc::Rc;
 
 use crate::command_line::CommandLine;
+use crate::formats::{csv::CsvOptions, Format};
 use crate::table::Table;
+use crate::text_view::TextView;
 use crate::undo_tree::{self, UndoTree};
+use crate::workbook::Workbook;
 
 #[derive(Eq, PartialEq, Clone, Copy)]
 pub enum Mode {
     Normal,
     Insert,
     Command,
+    View,
 }
 
 pub struct KeySequence {
@@ -26,9 +30,14 @@ pub struct TermInfo {
 pub struct Program<'a> {
     mode: Mode,
     file_path: String,
+    ///The format the file is saved in
+    pub file_format: Format,
+    ///Used when the file is saved as csv
+    pub csv_options: CsvOptions,
     undo_tree: undo_tree::UndoTree,
-    pub table: &'a mut Table,
+    pub book: &'a mut Workbook,
     pub command_line: &'a mut CommandLine,
+    pub text_view: TextView,
     pub running: bool,
     pub term_info: TermInfo,
     pub previous_tables: Vec<Table>,
@@ -37,27 +46,30 @@ pub struct Program<'a> {
 impl Program<'_> {
     pub fn new<'a>(
         fp: &str,
-        table: &'a mut Table,
+        book: &'a mut Workbook,
         command_line: &'a mut CommandLine,
     ) -> Program<'a> {
-        let rows = table.get_rows();
+        let state = book.table().get_state();
         Program {
             command_line,
+            text_view: TextView::new(),
             mode: Mode::Normal,
-            table,
+            book,
             file_path: fp.to_string(),
+            file_format: Format::Tsheet,
+            csv_options: CsvOptions::default(),
             running: true,
             term_info: TermInfo {
                 cols: 30,
                 lines: 20,
             },
             previous_tables: vec![],
-            undo_tree: UndoTree::new(Box::new(rows), 0),
+            undo_tree: UndoTree::new(Box::new(state), 0),
         }
     }
 
     pub fn save_state(&mut self) {
-        self.undo_tree = self.undo_tree.save(Box::new(self.table.get_rows()));
+        self.undo_tree = self.undo_tree.save(Box::new(self.book.table().get_state()));
     }
 
     pub fn undo(&mut self) {
@@ -66,7 +78,7 @@ impl Program<'_> {
             None => self.command_line.print("Cannot undo"),
             Some(t) => {
                 self.command_line.print(&format!("Undo {}", t.get_id()));
-                self.table.set_data(*t.get_state());
+                self.book.table_mut().set_state(*t.get_state());
                 self.undo_tree = *t;
             }
         }
@@ -78,12 +90,20 @@ impl Program<'_> {
             None => self.command_line.print("Cannot redo"),
             Some(t) => {
                 self.command_line.print(&format!("Redo {}", t.get_id()));
-                self.table.set_data(*t.get_state());
+                self.book.table_mut().set_state(*t.get_state());
                 self.undo_tree = *t;
             }
         }
     }
 
+    ///Edits another sheet in the file, undo history is only kept for the sheet being edited
+    pub fn switch_sheet(&mut self, index: usize) {
+        if self.book.switch(index) {
+            let state = self.book.table().get_state();
+            self.undo_tree = UndoTree::new(Box::new(state), 0);
+        }
+    }
+
     pub fn current_mode(&self) -> Mode {
         return self.mode;
     }
@@ -107,7 +127,7 @@ impl Program<'_> {
         let mut count = String::new();
         let mut buf = [0; 32]; //consume enough bytes to store utf-8, 32 bytes should be enough
         match self.mode {
-            Mode::Normal => loop {
+            Mode::Normal | Mode::View => loop {
                 let bytes_read = reader.read(&mut buf).unwrap();
                 let key = String::from_utf8(buf[0..bytes_read].to_vec()).unwrap();
                 let ch = buf[0];
@@ -0,0 +1,376 @@
+use crate::{
+    decimal::{Decimal, RoundingMode},
+    table::{self, Position},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct IterationSettings {
+    pub max_iterations: usize,
+    pub epsilon: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DecimalSettings {
+    ///Digits shown after the point, None shows the exact value
+    pub places: Option<u32>,
+    pub rounding: RoundingMode,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IterationStatus {
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+///How numbers in a range of cells are shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberFormat {
+    ///A fixed number of digits after the point
+    Fixed(u32),
+    ///Multiplied by 100 and followed by %
+    Percent(u32),
+    ///An exponent with a fixed number of digits after the point, eg: 1.50e3
+    Scientific(u32),
+}
+
+impl NumberFormat {
+    ///Parses `fixed|percent|sci [places]`
+    pub fn parse<'a>(mut args: impl Iterator<Item = &'a str>) -> Option<NumberFormat> {
+        let kind = args.next()?;
+        let places = match args.next() {
+            Some(p) => p.parse().ok()?,
+            None => 2,
+        };
+        return match kind {
+            "fixed" => Some(NumberFormat::Fixed(places)),
+            "percent" => Some(NumberFormat::Percent(places)),
+            "sci" => Some(NumberFormat::Scientific(places)),
+            _ => None,
+        };
+    }
+
+    pub fn name(&self) -> String {
+        return match self {
+            NumberFormat::Fixed(places) => format!("fixed {}", places),
+            NumberFormat::Percent(places) => format!("percent {}", places),
+            NumberFormat::Scientific(places) => format!("sci {}", places),
+        };
+    }
+
+    ///Formats canonical number text, the result is canonical apart from any suffix
+    pub fn apply(&self, text: &str) -> Option<String> {
+        let d = Decimal::parse(text)?;
+        return match self {
+            NumberFormat::Fixed(places) => Some(d.format_fixed(*places, RoundingMode::HalfEven)),
+            NumberFormat::Percent(places) => {
+                let percent = d.mul(&Decimal::parse("100")?)?;
+                Some(percent.format_fixed(*places, RoundingMode::HalfEven) + "%")
+            }
+            NumberFormat::Scientific(places) => Some(format!(
+                "{:.places$e}",
+                d.to_f64(),
+                places = *places as usize
+            )),
+        };
+    }
+}
+
+///A name that can be used in equations in place of a cell or range
+#[derive(Debug, Clone)]
+pub struct NamedRange {
+    pub name: String,
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone)]
+pub struct RangeFormat {
+    pub start: Position,
+    pub end: Position,
+    pub format: NumberFormat,
+}
+
+///Another file whose cells equations can use as `name!$A1`
+#[derive(Debug, Clone)]
+pub struct Include {
+    ///Relative to the directory of the file that includes it
+    pub path: String,
+    pub name: String,
+}
+
+///Sheet wide settings, these are saved in the file as `%name args` directives
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    ///Shown in the top left corner of the sheet
+    pub name: Option<String>,
+    ///Rows and columns that stay on screen when scrolling
+    pub frozen_rows: usize,
+    pub frozen_cols: usize,
+    pub named_ranges: Vec<NamedRange>,
+    ///Later formats take priority over earlier ones
+    pub formats: Vec<RangeFormat>,
+    pub includes: Vec<Include>,
+    pub iteration: Option<IterationSettings>,
+    ///Seed for volatile functions, when unset a random seed is picked each time the file is opened
+    pub seed: Option<u64>,
+    ///Write equations as canonical formula text when saving
+    pub format_on_save: bool,
+    ///Evaluate numbers in base 10 instead of as floats
+    pub decimal: Option<DecimalSettings>,
+}
+
+impl Settings {
+    ///Applies a directive such as `iterate 100 0.001`, returns false if the directive is not known
+    ///or its arguments are not valid
+    pub fn apply_directive(&mut self, directive: &str) -> bool {
+        let mut args = directive.split_whitespace();
+        match args.next() {
+            Some("name") => {
+                let name = directive.trim_start()["name".len()..].trim();
+                self.name = Some(name.to_string()).filter(|n| n.len() > 0);
+                return true;
+            }
+            Some("freeze") => {
+                let rows = args.next().unwrap_or("0").parse();
+                let cols = args.next().unwrap_or("0").parse();
+                if let (Ok(rows), Ok(cols)) = (rows, cols) {
+                    self.frozen_rows = rows;
+                    self.frozen_cols = cols;
+                    return true;
+                }
+                return false;
+            }
+            Some("range") => {
+                let name = match args.next() {
+                    Some(n) if is_range_name(n) => n,
+                    _ => return false,
+                };
+                self.named_ranges.retain(|r| r.name != name);
+                //a range without cells removes the name
+                if let Some(cells) = args.next() {
+                    let (start, end) = match table::parse_range_name(cells) {
+                        Some(r) => r,
+                        None => return false,
+                    };
+                    self.named_ranges.push(NamedRange {
+                        name: name.to_string(),
+                        start,
+                        end,
+                    });
+                }
+                return true;
+            }
+            Some("include") => {
+                let include = match parse_include(directive.trim_start()["include".len()..].trim()) {
+                    Some(i) => i,
+                    None => return false,
+                };
+                self.includes.retain(|i| i.name != include.name);
+                self.includes.push(include);
+                return true;
+            }
+            Some("format") => {
+                let (start, end) = match args.next().and_then(table::parse_range_name) {
+                    Some(r) => r,
+                    None => return false,
+                };
+                let rest: Vec<&str> = args.collect();
+                if rest == ["none"] {
+                    self.formats
+                        .retain(|f| !(f.start == start && f.end == end));
+                    return true;
+                }
+                match NumberFormat::parse(rest.into_iter()) {
+                    Some(format) => {
+                        self.formats.push(RangeFormat { start, end, format });
+                        return true;
+                    }
+                    None => return false,
+                }
+            }
+            Some("iterate") => match parse_iteration_args(args) {
+                Ok(iteration) => {
+                    self.iteration = iteration;
+                    return true;
+                }
+                Err(_) => return false,
+            },
+            Some("seed") => {
+                self.seed = args.next().and_then(|s| s.parse().ok());
+                return true;
+            }
+            Some("decimal") => {
+                self.decimal = parse_decimal_args(args);
+                return true;
+            }
+            Some("fmtonsave") => {
+                self.format_on_save = true;
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    pub fn to_directives(&self) -> Vec<String> {
+        let mut directives: Vec<String> = vec![];
+        if let Some(name) = &self.name {
+            directives.push(format!("name {}", name));
+        }
+        if self.frozen_rows > 0 || self.frozen_cols > 0 {
+            directives.push(format!("freeze {} {}", self.frozen_rows, self.frozen_cols));
+        }
+        for range in &self.named_ranges {
+            directives.push(format!(
+                "range {} {}",
+                range.name,
+                table::range_name(&range.start, &range.end)
+            ));
+        }
+        for include in &self.includes {
+            directives.push(format!("include \"{}\" as {}", include.path, include.name));
+        }
+        for format in &self.formats {
+            directives.push(format!(
+                "format {} {}",
+                table::range_name(&format.start, &format.end),
+                format.format.name()
+            ));
+        }
+        if let Some(iteration) = &self.iteration {
+            directives.push(format!(
+                "iterate {} {}",
+                iteration.max_iterations, iteration.epsilon
+            ));
+        }
+        if let Some(seed) = self.seed {
+            directives.push(format!("seed {}", seed));
+        }
+        if let Some(decimal) = &self.decimal {
+            directives.push(match decimal.places {
+                Some(places) => format!("decimal {} {}", places, decimal.rounding.name()),
+                None => format!("decimal {}", decimal.rounding.name()),
+            });
+        }
+        if self.format_on_save {
+            directives.push("fmtonsave".to_string());
+        }
+        return directives;
+    }
+
+    pub fn find_named_range(&self, name: &str) -> Option<&NamedRange> {
+        return self.named_ranges.iter().find(|r| r.name == name);
+    }
+
+    pub fn find_format(&self, pos: &Position) -> Option<NumberFormat> {
+        return self
+            .formats
+            .iter()
+            .rev()
+            .find(|f| table::is_in_range(pos, &f.start, &f.end))
+            .map(|f| f.format);
+    }
+}
+
+///Parses `"path" as name`
+fn parse_include(text: &str) -> Option<Include> {
+    let (path, rest) = text.strip_prefix('"')?.split_once('"')?;
+    let mut words = rest.split_whitespace();
+    if words.next() != Some("as") {
+        return None;
+    }
+    let name = words.next()?;
+    if words.next().is_some() || !is_range_name(name) || path.len() == 0 {
+        return None;
+    }
+    return Some(Include {
+        path: path.to_string(),
+        name: name.to_string(),
+    });
+}
+
+///Names of ranges are identifiers in equations, and must not look like a cell
+fn is_range_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    return chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && table::parse_position_name(name).is_none();
+}
+
+///Parses `<max iterations> [epsilon]`, `off` disables iteration. The error says which argument
+///is not valid
+pub fn parse_iteration_args<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+) -> Result<Option<IterationSettings>, String> {
+    let max_iterations = match args.next() {
+        None | Some("off") => return Ok(None),
+        Some(n) => match n.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("{} is not a number of iterations", n)),
+        },
+    };
+    let epsilon = match args.next() {
+        None => 0.001,
+        Some(e) => match e.parse::<f64>() {
+            Ok(e) if e.is_finite() && e >= 0.0 => e,
+            _ => return Err(format!("{} is not a maximum change", e)),
+        },
+    };
+    if let Some(arg) = args.next() {
+        return Err(format!("Unexpected argument {}", arg));
+    }
+    return Ok(Some(IterationSettings {
+        max_iterations,
+        epsilon,
+    }));
+}
+
+///Parses `[places] [rounding mode]`, `off` disables decimal mode
+pub fn parse_decimal_args<'a>(args: impl Iterator<Item = &'a str>) -> Option<DecimalSettings> {
+    let mut settings = DecimalSettings {
+        places: None,
+        rounding: RoundingMode::HalfEven,
+    };
+    for arg in args {
+        if arg == "off" {
+            return None;
+        } else if let Ok(places) = arg.parse() {
+            settings.places = Some(places);
+        } else if let Some(rounding) = RoundingMode::from_name(arg) {
+            settings.rounding = rounding;
+        }
+    }
+    return Some(settings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_iteration_args, Settings};
+
+    #[test]
+    fn parses_iteration_args() {
+        let iteration = parse_iteration_args("50 0.01".split_whitespace()).unwrap().unwrap();
+        assert_eq!(iteration.max_iterations, 50);
+        assert_eq!(iteration.epsilon, 0.01);
+        let iteration = parse_iteration_args("20".split_whitespace()).unwrap().unwrap();
+        assert_eq!(iteration.epsilon, 0.001);
+        assert!(parse_iteration_args("off".split_whitespace()).unwrap().is_none());
+        assert!(parse_iteration_args("".split_whitespace()).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_iteration_args() {
+        for args in ["abc", "-5", "0", "100 -0.1", "100 abc", "100 inf", "100 0.1 2"] {
+            assert!(parse_iteration_args(args.split_whitespace()).is_err(), "{}", args);
+        }
+    }
+
+    #[test]
+    fn invalid_iterate_directive_keeps_the_setting() {
+        let mut settings = Settings::default();
+        assert!(settings.apply_directive("iterate 10 0.5"));
+        assert!(!settings.apply_directive("iterate abc"));
+        assert_eq!(settings.iteration.unwrap().max_iterations, 10);
+    }
+}
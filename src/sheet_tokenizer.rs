@@ -1,4 +1,7 @@
-use std::str::Chars;
+use std::{fmt, str::Chars};
+
+///The newest version of the file format, written in the header as `#!tsheet 2`
+pub static SHEET_VERSION: u32 = 2;
 
 #[derive(Clone, Debug)]
 pub enum Token {
@@ -6,21 +9,73 @@ pub enum Token {
     RBracket,
     String(String),
     Expr(String),
-    Number(f64),
-    Err(char),
+    ///The number as written in the file so that no precision is lost
+    Number(String),
+    ///Text that is not a valid token, including strings and equations that are not closed
+    Err(String),
     Comma,
+    Directive(String),
+    ///The `#!` line at the start of the file, eg: tsheet 2
+    Header(String),
+    ///A line comment outside of a row, including the `#` or `//`
+    Comment(String),
+    ///A line with nothing on it outside of a row
+    BlankLine,
+}
+
+///A token and the line and column it starts at, both starting from 1
+#[derive(Clone, Debug)]
+pub struct SheetToken {
+    pub token: Token,
+    pub line: usize,
+    pub col: usize,
+}
+
+///A problem found while loading a file, the file is still loaded as well as possible
+#[derive(Clone, Debug)]
+pub struct LoadError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl LoadError {
+    pub fn new(tok: &SheetToken, message: String) -> LoadError {
+        return LoadError {
+            line: tok.line,
+            col: tok.col,
+            message,
+        };
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}:{}: {}", self.line, self.col, self.message);
+    }
 }
 
 struct Lexer<'a> {
-    tokens: Vec<Token>,
+    tokens: Vec<SheetToken>,
     chars: Chars<'a>,
     cur_char: Option<char>,
+    line: usize,
+    col: usize,
+    token_start: (usize, usize),
+    ///The line the last token ended on
+    last_line: usize,
+    in_row: bool,
 }
 
 impl Lexer<'_> {
     pub fn next(&mut self) -> Option<char> {
+        if let Some('\n') = self.cur_char {
+            self.line += 1;
+            self.col = 0;
+        }
         let c = self.chars.next();
         self.cur_char = c;
+        self.col += 1;
         return c;
     }
 
@@ -28,92 +83,311 @@ impl Lexer<'_> {
         return self.cur_char;
     }
 
+    pub fn peek(&self) -> Option<char> {
+        return self.chars.clone().next();
+    }
+
+    ///Marks the current character as the start of the next token
+    pub fn start_token(&mut self) {
+        self.token_start = (self.line, self.col);
+    }
+
     pub fn add_token(&mut self, tok: Token) {
-        self.tokens.push(tok);
+        self.last_line = self.line;
+        self.tokens.push(SheetToken {
+            token: tok,
+            line: self.token_start.0,
+            col: self.token_start.1,
+        });
     }
 
-    pub fn get_tokens(&self) -> Vec<Token> {
+    pub fn get_tokens(&self) -> Vec<SheetToken> {
         return self.tokens.clone();
     }
 }
 
-fn parse_string(lexer: &mut Lexer) -> String {
+///Reads a string up to the closing `"`, `\` escapes the next character. Returns the raw text as an
+///error if the string is not closed
+fn parse_string(lexer: &mut Lexer) -> Result<String, String> {
     let mut str = String::new();
+    let mut raw = String::from("\"");
     while let Some(ch) = lexer.next() {
+        raw += &ch.to_string();
+        if ch == '\\' {
+            match lexer.next() {
+                Some(escaped) => {
+                    str += &escaped.to_string();
+                    raw += &escaped.to_string();
+                }
+                None => break,
+            }
+            continue;
+        }
         if ch == '"' {
-            break;
+            return Ok(str);
         }
         str += &ch.to_string();
     }
-    return str;
+    return Err(raw);
 }
 
-fn parse_number(lexer: &mut Lexer) -> f64 {
+///Reads a number in any form that f64 can be written as, eg: -5, 1.5e-7, inf or NaN. Returns the
+///text that was read as an error if it is not a number
+fn parse_number(lexer: &mut Lexer) -> Result<String, String> {
     let mut text = lexer.get_cur_char().unwrap().to_string();
-    let mut is_dec = false;
+    let mut has_digits = text.chars().all(|c| c.is_ascii_digit());
+    let mut is_dec = text == ".";
+    let mut is_exp = false;
+    let is_word = text.chars().all(|c| c.is_ascii_alphabetic());
     while let Some(char) = lexer.next() {
-        if char == '.' && !is_dec {
+        let prev = text.chars().last().unwrap();
+        if is_word || (!has_digits && !is_dec && char.is_ascii_alphabetic()) {
+            //inf, infinity and nan
+            if !char.is_ascii_alphabetic() {
+                break;
+            }
+        } else if char.is_ascii_digit() {
+            has_digits = true;
+        } else if char == '.' && !is_dec && !is_exp {
             is_dec = true;
-            text += &char.to_string();
-        } else if char == '.' {
-            break;
-        } else if let '0'..='9' = char {
-            text += &char.to_string();
+        } else if (char == 'e' || char == 'E') && has_digits && !is_exp {
+            is_exp = true;
+        } else if (char == '-' || char == '+') && (prev == 'e' || prev == 'E') {
         } else {
             break;
         }
+        text += &char.to_string();
     }
 
-    let n: f64 = text.parse().expect("Could not parse number");
-    return n;
+    return match text.parse::<f64>() {
+        Ok(_) => Ok(text),
+        Err(_) => Err(text),
+    };
 }
 
-fn parse_expr(lexer: &mut Lexer) -> String {
+fn parse_directive(lexer: &mut Lexer) -> String {
     let mut str = String::new();
+    while let Some(char) = lexer.next() {
+        if char == '\n' {
+            break;
+        }
+        str += &char.to_string();
+    }
+    return str.trim().to_string();
+}
+
+///Reads an equation up to its closing `)`. Parentheses inside string literals are not counted,
+///and outside of string literals `\` escapes the next character. Returns the raw text as an error
+///if the equation is not closed
+fn parse_expr(lexer: &mut Lexer) -> Result<String, String> {
+    let mut str = String::new();
+    let mut raw = String::from("(");
     let mut paren_count = 1;
+    let mut in_string = false;
     while let Some(char) = lexer.next() {
-        if char == ')' {
+        raw += &char.to_string();
+        if in_string {
+            if char == '\\' {
+                //escapes inside string literals belong to the equation
+                str += &char.to_string();
+                match lexer.next() {
+                    Some(escaped) => {
+                        str += &escaped.to_string();
+                        raw += &escaped.to_string();
+                    }
+                    None => break,
+                }
+                continue;
+            } else if char == '"' {
+                in_string = false;
+            }
+        } else if char == '\\' {
+            match lexer.next() {
+                Some(escaped) => {
+                    str += &escaped.to_string();
+                    raw += &escaped.to_string();
+                }
+                None => break,
+            }
+            continue;
+        } else if char == '"' {
+            in_string = true;
+        } else if char == ')' {
             paren_count -= 1;
         } else if char == '(' {
             paren_count += 1;
         }
         if paren_count == 0 {
+            return Ok(str);
+        }
+        str += &char.to_string();
+    }
+    return Err(raw);
+}
+
+///Escapes text so that parse_string reads it back unchanged
+pub fn escape_string(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if ch == '\\' || ch == '"' {
+            escaped += "\\";
+        }
+        escaped += &ch.to_string();
+    }
+    return escaped;
+}
+
+///Escapes equation text so that parse_expr reads it back unchanged. Equations that parse_expr
+///already reads correctly, which is any equation with balanced parentheses and closed strings,
+///are left as is
+pub fn escape_expr(text: &str) -> String {
+    let mut paren_count = 1;
+    let mut in_string = false;
+    let mut in_escape = false;
+    let mut needs_escape = false;
+    for ch in text.chars() {
+        if in_escape {
+            in_escape = false;
+        } else if in_string {
+            match ch {
+                '\\' => in_escape = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+        } else {
+            match ch {
+                '\\' => needs_escape = true,
+                '"' => in_string = true,
+                '(' => paren_count += 1,
+                ')' => paren_count -= 1,
+                _ => (),
+            }
+            if paren_count == 0 {
+                needs_escape = true;
+            }
+        }
+    }
+    if !needs_escape && !in_string && !in_escape && paren_count == 1 {
+        return text.to_string();
+    }
+    //escaping every quote means none of the text is read as a string literal
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if ch == '\\' || ch == '"' || ch == '(' || ch == ')' {
+            escaped += "\\";
+        }
+        escaped += &ch.to_string();
+    }
+    return escaped;
+}
+
+///Reads a comment to the end of the line, keeping the `#` or `//` so it can be written back as is
+fn parse_comment(lexer: &mut Lexer) -> String {
+    let mut str = lexer.get_cur_char().unwrap().to_string();
+    while let Some(char) = lexer.next() {
+        if char == '\n' {
             break;
         }
         str += &char.to_string();
     }
+    return str.trim_end().to_string();
+}
+
+///Reads the version from a header such as `tsheet 2`
+pub fn parse_header(header: &str) -> Option<u32> {
+    let mut words = header.split_whitespace();
+    if words.next() != Some("tsheet") {
+        return None;
+    }
+    let version = words.next()?.parse().ok()?;
+    if words.next().is_some() {
+        return None;
+    }
+    return Some(version);
+}
+
+///Reads text that is not a token up to the next separator
+fn parse_err(lexer: &mut Lexer) -> String {
+    let mut str = lexer.get_cur_char().unwrap().to_string();
+    while let Some(char) = lexer.next() {
+        match char {
+            ',' | '[' | ']' | '(' | '"' | '%' | ' ' | '\n' | '\t' | '\r' => break,
+            _ => str += &char.to_string(),
+        }
+    }
     return str;
 }
 
-pub fn parse(contents: &str) -> Vec<Token> {
+pub fn parse(contents: &str) -> Vec<SheetToken> {
     let mut lexer = Lexer {
         tokens: vec![],
         chars: contents.chars(),
         cur_char: None,
+        line: 1,
+        col: 0,
+        token_start: (1, 1),
+        last_line: 0,
+        in_row: false,
     };
 
     lexer.next();
 
+    //files without a header are version 1
+    if contents.starts_with("#!") {
+        lexer.start_token();
+        lexer.next();
+        let tok = Token::Header(parse_directive(&mut lexer));
+        lexer.add_token(tok);
+        lexer.next();
+    }
+
     loop {
         if let None = lexer.cur_char {
             break;
         } else if let Some(ch) = lexer.cur_char {
+            lexer.start_token();
             let tok = match ch {
-                ']' => Token::RBracket,
-                '[' => Token::LBracket,
+                //comments and blank lines are kept so that saving does not remove them
+                '#' if !lexer.in_row => Token::Comment(parse_comment(&mut lexer)),
+                '/' if !lexer.in_row && lexer.peek() == Some('/') => {
+                    Token::Comment(parse_comment(&mut lexer))
+                }
+                '\n' if !lexer.in_row && lexer.last_line < lexer.line => Token::BlankLine,
+                ']' => {
+                    lexer.in_row = false;
+                    Token::RBracket
+                }
+                '[' => {
+                    lexer.in_row = true;
+                    Token::LBracket
+                }
                 ',' => Token::Comma,
-                '(' => Token::Expr(parse_expr(&mut lexer)),
-                '"' => Token::String(parse_string(&mut lexer)),
+                '(' => match parse_expr(&mut lexer) {
+                    Ok(expr) => Token::Expr(expr),
+                    Err(raw) => Token::Err(raw),
+                },
+                '"' => match parse_string(&mut lexer) {
+                    Ok(str) => Token::String(str),
+                    Err(raw) => Token::Err(raw),
+                },
+                '%' => Token::Directive(parse_directive(&mut lexer)),
                 ' ' | '\n' | '\t' | '\r' => {
                     lexer.next();
                     continue;
                 }
-                '0'..='9' => {
-                    let tok = Token::Number(parse_number(&mut lexer));
+                '0'..='9' | '-' | '+' | '.' | 'i' | 'I' | 'n' | 'N' => {
+                    let tok = match parse_number(&mut lexer) {
+                        Ok(n) => Token::Number(n),
+                        Err(text) => Token::Err(text),
+                    };
+                    lexer.add_token(tok);
+                    continue;
+                }
+                _ => {
+                    let tok = Token::Err(parse_err(&mut lexer));
                     lexer.add_token(tok);
                     continue;
                 }
-                _ => Token::Err(ch),
             };
             lexer.add_token(tok);
             lexer.next();
@@ -122,3 +396,76 @@ pub fn parse(contents: &str) -> Vec<Token> {
 
     return lexer.get_tokens();
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::locale::Locale;
+    use crate::table::{Data, Table};
+    use crate::workbook::Workbook;
+
+    ///Characters that have a meaning in tsheet files, mixed with ordinary text
+    const CHARS: &[char] = &[
+        '"', '\\', '(', ')', '[', ']', ',', '%', '#', '/', ' ', '\n', 'a', 'Z', '1', '$', '+',
+    ];
+
+    const NUMBERS: &[&str] = &[
+        "0", "-1", "+2", "0.1", ".5", "5.", "1e3", "2.5E-7", "12345678901234567.89", "inf",
+        "-inf", "NaN",
+    ];
+
+    fn random_text(rng: &mut StdRng) -> String {
+        let len = rng.gen_range(0..12);
+        return (0..len)
+            .map(|_| CHARS[rng.gen_range(0..CHARS.len())])
+            .collect();
+    }
+
+    fn random_cell(rng: &mut StdRng) -> Data {
+        match rng.gen_range(0..4) {
+            0 => Data::String(random_text(rng)),
+            1 => Data::Number(NUMBERS[rng.gen_range(0..NUMBERS.len())].to_string()),
+            2 => Data::Number(rng.gen_range(-1e9..1e9f64).to_string()),
+            _ => Data::equation(random_text(rng), None),
+        }
+    }
+
+    ///What the cell holds, without the cached value and parsed tree of equations
+    fn cell_text(data: &Data) -> (u8, String) {
+        match data {
+            Data::String(s) => (0, s.clone()),
+            Data::Number(n) => (1, n.clone()),
+            Data::Equation(e, ..) => (2, e.clone()),
+        }
+    }
+
+    fn row_texts(table: &Table) -> Vec<Vec<(u8, String)>> {
+        return table
+            .get_rows()
+            .iter()
+            .map(|row| row.iter().map(cell_text).collect())
+            .collect();
+    }
+
+    #[test]
+    fn cells_round_trip_through_a_file() {
+        let mut rng = StdRng::seed_from_u64(35);
+        for _ in 0..500 {
+            let width = rng.gen_range(1..5);
+            let rows: Vec<Vec<Data>> = (0..rng.gen_range(1..5))
+                .map(|_| (0..width).map(|_| random_cell(&mut rng)).collect())
+                .collect();
+            let book = Workbook::new(Table::from_rows(rows, Locale::canonical()));
+            let text = book.to_sheet();
+            let (loaded, errors) = Workbook::from_sheet_tokens(super::parse(&text));
+            assert!(errors.is_empty(), "{:?} loading\n{}", errors, text);
+            assert_eq!(
+                row_texts(book.table()),
+                row_texts(loaded.table()),
+                "loading\n{}",
+                text
+            );
+        }
+    }
+}
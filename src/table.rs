@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 macro_rules! IF {
     ($e:expr, true=>  $t:expr, false=> $f:expr) => {
         if $e {
@@ -10,17 +8,27 @@ macro_rules! IF {
     };
 }
 
+use std::{path::PathBuf, rc::Rc};
+
 use crate::{
-    calculator::{self, calculate},
-    position_parser, program, sheet_tokenizer,
+    calculator::{self, calculate_cell},
+    include::{self, IncludedSheet},
+    locale::Locale,
+    position_parser, program,
+    settings::{IterationStatus, Settings},
+    sheet_tokenizer,
+    workbook::Workbook,
 };
 
+///How many rows or columns setting a cell outside the sheet may add
+pub const MAX_GROWTH: usize = 10000;
+
 pub fn base_26_to_10(n: String) -> usize {
     let mut ans = 0;
     let reversed = n.chars().rev().collect::<String>();
     let base: usize = 26;
     for i in 0..reversed.len() {
-        let ch = reversed.chars().nth(i).unwrap() as u8;
+        let ch = reversed.chars().nth(i).unwrap().to_ascii_lowercase() as u8;
         ans += ((ch - 97) as usize) * (base.pow(i as u32));
     }
     return ans;
@@ -36,42 +44,134 @@ pub fn base_10_to_col_num(mut n: usize) -> String {
     return col_name;
 }
 
+///The human name of a position, eg: A1
+pub fn position_name(pos: &Position) -> String {
+    return format!("{}{}", base_10_to_col_num(pos.col + 1), pos.row + 1);
+}
+
+///Parses a name such as A1, None if text is not exactly a position
+pub fn parse_position_name(text: &str) -> Option<Position> {
+    let text = text.strip_prefix('$').unwrap_or(text);
+    let digits = text.find(|c: char| c.is_ascii_digit())?;
+    let (col, row) = text.split_at(digits);
+    //longer column names would overflow, and no sheet is that wide
+    if col.len() == 0 || col.len() > 7 || !col.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    if !row.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let row: usize = row.parse().ok()?;
+    if row == 0 {
+        return None;
+    }
+    return Some(Position {
+        row: row - 1,
+        col: base_26_to_10(col.to_lowercase()),
+    });
+}
+
+///Parses a cell such as A1 or a range such as A1:B2
+pub fn parse_range_name(text: &str) -> Option<(Position, Position)> {
+    return match text.split_once(':') {
+        Some((start, end)) => Some((parse_position_name(start)?, parse_position_name(end)?)),
+        None => parse_position_name(text).map(|p| (p, p)),
+    };
+}
+
+///The human name of a range, or of a cell if the range is one cell
+pub fn range_name(start: &Position, end: &Position) -> String {
+    if start == end {
+        return position_name(start);
+    }
+    return format!("{}:{}", position_name(start), position_name(end));
+}
+
+pub fn is_in_range(pos: &Position, start: &Position, end: &Position) -> bool {
+    return pos.row >= start.row && pos.row <= end.row && pos.col >= start.col && pos.col <= end.col;
+}
+
 #[derive(Debug, Clone)]
 pub enum Data {
     Number(String),
-    Equation(String, Option<calculator::CalculatorValue>),
+    ///The equation's text, its cached value, and its parsed tree
+    Equation(String, Option<calculator::CalculatorValue>, Rc<calculator::Node>),
     String(String),
 }
 
 fn handle_equation(
-    table: &Table,
-    expr: &str,
+    book: &Workbook,
+    pos: &Position,
+    tree: &calculator::Node,
     _invalid_references: &mut Vec<(usize, usize)>,
 ) -> Result<String, &'static str> {
-    let mut map: HashMap<String, calculator::CalculatorValue> = HashMap::new();
-
-    map.insert(
-        "%recursion".to_string(),
-        calculator::CalculatorValue::Number(0.0),
-    );
-
-    let ans = match calculate(expr, &mut map, table) {
-        Ok(calculator::CalculatorValue::String(s)) => s.to_string(),
-        Ok(calculator::CalculatorValue::Number(n)) => n.to_string(),
-        Ok(calculator::CalculatorValue::Range(x, y)) => format!("{:?}..{:?}", x, y),
-        Err(e) => match e {
-            calculator::CalculatorError::RecursionLimit => "Err#1: Recursion limit reached",
-            calculator::CalculatorError::InvalidBinaryOp(_) => "Err#2: Invalid binary operation",
-        }
-        .to_owned(),
+    let mut map = calculator::cell_symbols(pos);
+
+    let ans = match calculate_cell(pos, tree, &mut map, book, None) {
+        Ok(v) => book.table().format_result_at(pos, &v),
+        Err(e) => error_text(&e).to_owned(),
     };
     return Ok(ans);
 }
 
+///The text shown for an equation that could not be evaluated
+pub fn error_text(error: &calculator::CalculatorError) -> &'static str {
+    return match error {
+        calculator::CalculatorError::RecursionLimit => "Err#1: Recursion limit reached",
+        calculator::CalculatorError::InvalidBinaryOp(_) => "Err#2: Invalid binary operation",
+        calculator::CalculatorError::DivisionByZero => "Err#3: Division by zero",
+        calculator::CalculatorError::Overflow => "Err#4: Number too large",
+        calculator::CalculatorError::UnitMismatch => "Err#5: Mismatched units",
+        calculator::CalculatorError::UnknownUnit => "Err#6: Unknown unit",
+        calculator::CalculatorError::UnknownSheet => "Err#7: Unknown sheet",
+        calculator::CalculatorError::InvalidArgument => "Err#8: Invalid argument",
+        calculator::CalculatorError::CircularReference => "Err#9: Circular reference",
+    };
+}
+
+pub fn format_value(value: &calculator::CalculatorValue) -> String {
+    match value {
+        calculator::CalculatorValue::String(s) => s.to_string(),
+        calculator::CalculatorValue::Number(n) => n.to_string(),
+        calculator::CalculatorValue::Decimal(d) => d.to_string(),
+        calculator::CalculatorValue::Quantity(n, unit) => format!("{} {}", n, unit.name()),
+        calculator::CalculatorValue::Range(x, y) => {
+            format!("{}:{}", position_name(x), position_name(y))
+        }
+        calculator::CalculatorValue::Array(items) => items
+            .iter()
+            .map(format_value)
+            .collect::<Vec<String>>()
+            .join(", "),
+    }
+}
+
 impl Data {
-    fn display_number(&self, n: &str, max_width: usize, is_hovered: bool) -> String {
-        let new_text = n.to_owned();
-        return IF!(n.len() > max_width && !is_hovered,
+    ///Creates an equation, parsing the text so it does not have to be parsed each time it is
+    ///evaluated
+    pub fn equation(text: String, cache: Option<calculator::CalculatorValue>) -> Data {
+        let tree = calculator::build_tree(&text);
+        return Data::Equation(text, cache, tree);
+    }
+
+    ///The width of a column if this is a cell in the row of column sizes
+    fn to_column_size(&self) -> Option<usize> {
+        if let Data::Number(n) = self {
+            return n.parse().ok();
+        }
+        return None;
+    }
+
+    fn display_number(
+        &self,
+        book: &Workbook,
+        pos: &Position,
+        n: &str,
+        max_width: usize,
+        is_hovered: bool,
+    ) -> String {
+        let new_text = book.table().format_number_at(pos, n);
+        return IF!(new_text.len() > max_width && !is_hovered,
             true=> new_text[0..max_width].to_string(),
             false=> format!("{:<max_width$}", new_text, max_width = max_width)
         );
@@ -79,7 +179,8 @@ impl Data {
 
     fn display_equation(
         &self,
-        table: &Table,
+        book: &Workbook,
+        pos: &Position,
         e: &str,
         max_width: usize,
         do_equations: bool,
@@ -88,16 +189,12 @@ impl Data {
         if !do_equations {
             return format!("{:<max_width$}", e, max_width = max_width);
         }
-        let mut invalid_refs: Vec<(usize, usize)> = vec![];
-        let ans = handle_equation(table, e, &mut invalid_refs);
-        if let Ok(a) = ans {
-            if a.len() > max_width && !is_hovered {
-                return a[0..max_width].to_string();
-            }
-            return format!("{:<max_width$}", a, max_width = max_width);
-        } else {
-            return format!("{:<max_width$}", "Inf ref", max_width = max_width);
+        //in iterative mode this is the result of the last iteration
+        let a = self.value_text(book, pos);
+        if a.len() > max_width && !is_hovered {
+            return a[0..max_width].to_string();
         }
+        return format!("{:<max_width$}", a, max_width = max_width);
     }
 
     fn display_string(&self, s: &str, max_width: usize, is_hovered: bool) -> String {
@@ -109,35 +206,98 @@ impl Data {
         }
     }
 
+    ///The result of an equation in the sheet being edited, in iterative mode the result of the
+    ///last iteration. None if this is not an equation
+    pub fn evaluate(
+        &self,
+        book: &Workbook,
+        pos: &Position,
+    ) -> Option<Result<calculator::CalculatorValue, calculator::CalculatorError>> {
+        match self {
+            Data::Equation(_, Some(v), _) if book.table().is_iterative() => Some(Ok(v.clone())),
+            Data::Equation(_, _, tree) => {
+                let mut map = calculator::cell_symbols(pos);
+                Some(calculate_cell(pos, tree, &mut map, book, None))
+            }
+            _ => None,
+        }
+    }
+
+    ///The text shown for this cell in the sheet being edited, with equations evaluated
+    pub fn value_text(&self, book: &Workbook, pos: &Position) -> String {
+        let table = book.table();
+        match self {
+            Data::Number(n) => table.format_number_at(pos, n),
+            Data::String(s) => s.clone(),
+            Data::Equation(_, Some(v), _) if table.is_iterative() => table.format_result_at(pos, v),
+            Data::Equation(_, _, tree) => {
+                let mut invalid_refs: Vec<(usize, usize)> = vec![];
+                match handle_equation(book, pos, tree, &mut invalid_refs) {
+                    Ok(a) => a,
+                    Err(e) => e.to_string(),
+                }
+            }
+        }
+    }
+
     pub fn display(
         &self,
-        table: &Table,
+        book: &Workbook,
+        pos: &Position,
         max_width: usize,
         do_equations: bool,
         is_hovered: bool,
     ) -> String {
         match self {
-            Data::Number(n) => self.display_number(n, max_width, is_hovered),
+            Data::Number(n) => self.display_number(book, pos, n, max_width, is_hovered),
             Data::String(s) => self.display_string(s, max_width, is_hovered),
             Data::Equation(e, ..) => {
-                self.display_equation(table, e, max_width, do_equations, is_hovered)
+                self.display_equation(book, pos, e, max_width, do_equations, is_hovered)
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
     pub row: usize,
     pub col: usize,
 }
 
+///Comment and blank lines written above a row, and a comment at the end of the row's line
+#[derive(Debug, Clone, Default)]
+pub struct RowComments {
+    pub above: Vec<String>,
+    pub trailing: Option<String>,
+}
+
+///The parts of a table that are saved for undo
+#[derive(Debug, Clone)]
+pub struct TableState {
+    pub rows: Vec<Vec<Data>>,
+    pub row_comments: Vec<RowComments>,
+}
+
 #[derive(Debug)]
 pub struct Table {
     rows: Vec<Vec<Data>>,
     columns: Vec<Vec<Data>>,
+    ///One for each row
+    row_comments: Vec<RowComments>,
+    ///Comments before settings, with the name of the setting they are above, eg: widths
+    header_comments: Vec<(String, Vec<String>)>,
+    ///Comments after the last row
+    footer_comments: Vec<String>,
+    included: Vec<IncludedSheet>,
+    ///This sheet's file, after the files that include it
+    include_chain: Vec<PathBuf>,
     current_pos: Position,
     column_sizes: Vec<usize>,
+    settings: Settings,
+    iteration_status: Option<IterationStatus>,
+    random_seed: u64,
+    recalc_generation: u64,
+    locale: Locale,
 }
 
 pub enum Direction {
@@ -166,8 +326,193 @@ fn largest_list_in_2d_array<T>(array: &Vec<Vec<T>>) -> usize {
 }
 
 impl Table {
+    pub fn get_settings(&self) -> &Settings {
+        return &self.settings;
+    }
+
+    pub fn get_settings_mut(&mut self) -> &mut Settings {
+        return &mut self.settings;
+    }
+
+    ///Seed used by volatile functions such as rand(), this only changes on :recalc or when the
+    ///sheet's seed is changed
+    pub fn volatile_seed(&self) -> u64 {
+        let seed = self.settings.seed.unwrap_or(self.random_seed);
+        return seed ^ self.recalc_generation.wrapping_mul(0x9E3779B97F4A7C15);
+    }
+
+    ///Recalculates volatile functions
+    pub fn recalc_volatile(&mut self) {
+        self.recalc_generation += 1;
+    }
+
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.settings.seed = seed;
+        self.recalc_generation = 0;
+    }
+
+    pub fn is_decimal(&self) -> bool {
+        return self.settings.decimal.is_some();
+    }
+
+    ///Formats the value of an equation, in decimal mode numbers are rounded to the sheet's
+    ///number of places
+    ///Numbers are also written with the locale's separators
+    pub fn format_result(&self, value: &calculator::CalculatorValue) -> String {
+        type V = calculator::CalculatorValue;
+        match value {
+            V::Number(..) | V::Decimal(..) => {
+                let text = match (self.settings.decimal, value.to_decimal()) {
+                    (Some(decimal), Some(d)) => match decimal.places {
+                        Some(places) => d.format_fixed(places, decimal.rounding),
+                        None => format_value(value),
+                    },
+                    _ => format_value(value),
+                };
+                return self.locale.format_number(&text);
+            }
+            V::Quantity(n, unit) => {
+                return format!("{} {}", self.locale.format_number(&n.to_string()), unit.name());
+            }
+            V::Array(items) => {
+                let seperator = IF!(self.locale.decimal_separator == ',', true => "; ", false => ", ");
+                return items
+                    .iter()
+                    .map(|i| self.format_result(i))
+                    .collect::<Vec<String>>()
+                    .join(seperator);
+            }
+            V::String(..) | V::Range(..) => return format_value(value),
+        }
+    }
+
+    ///Formats the result of the equation at pos, using the number format for pos if there is one
+    pub fn format_result_at(&self, pos: &Position, value: &calculator::CalculatorValue) -> String {
+        type V = calculator::CalculatorValue;
+        if let (V::Number(..) | V::Decimal(..), Some(_)) = (value, self.settings.find_format(pos)) {
+            if let Some(d) = value.to_decimal() {
+                return self.format_number_at(pos, &d.to_string());
+            }
+        }
+        return self.format_result(value);
+    }
+
+    ///Formats canonical number text with the number format for pos and the locale
+    pub fn format_number_at(&self, pos: &Position, n: &str) -> String {
+        let text = match self.settings.find_format(pos).and_then(|f| f.apply(n)) {
+            Some(t) => t,
+            None => n.to_string(),
+        };
+        return match text.strip_suffix('%') {
+            Some(number) => self.locale.format_number(number) + "%",
+            None => self.locale.format_number(&text),
+        };
+    }
+
+    ///Loads the files named by `%include`, chain ends with this sheet's own file, and starts with
+    ///the files that include it. Returns the problems found
+    pub fn load_includes(&mut self, chain: Vec<PathBuf>) -> Vec<String> {
+        self.include_chain = chain;
+        self.included = vec![];
+        return self.reload_includes();
+    }
+
+    ///Loads included files that were added or have changed since they were loaded
+    pub fn reload_includes(&mut self) -> Vec<String> {
+        return include::sync_includes(
+            &self.settings.includes,
+            &mut self.included,
+            &self.include_chain,
+        );
+    }
+
+    ///The file included as name
+    pub fn find_included(&self, name: &str) -> Option<&Workbook> {
+        return self
+            .included
+            .iter()
+            .find(|s| s.name == name)
+            .and_then(|s| s.book.as_ref());
+    }
+
+    ///Uses the file and locale of other, for a sheet added to the file other is in
+    pub fn join_file(&mut self, other: &Table) {
+        self.include_chain = other.include_chain.clone();
+        self.locale = other.locale;
+    }
+
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.locale = locale;
+    }
+
+    ///Converts text typed by the user into a cell, numbers are stored in canonical form
+    fn data_from_text(&self, text: String) -> Data {
+        match self.locale.parse_number(&text) {
+            Some(n) => Data::Number(n),
+            None => Data::String(text),
+        }
+    }
+
+    pub fn is_iterative(&self) -> bool {
+        return self.settings.iteration.is_some();
+    }
+
+    ///Where every equation is, in the order they are iterated
+    pub fn equation_positions(&self) -> Vec<Position> {
+        let mut equations: Vec<Position> = vec![];
+        for (row_no, row) in self.rows.iter().enumerate() {
+            for (col_no, item) in row.iter().enumerate() {
+                if let Data::Equation(..) = item {
+                    equations.push(Position {
+                        row: row_no,
+                        col: col_no,
+                    });
+                }
+            }
+        }
+        return equations;
+    }
+
+    ///Sets the result of the last iteration, values cached by iteration are cleared when
+    ///iteration stops
+    pub fn set_iteration_status(&mut self, status: Option<IterationStatus>) {
+        if status.is_none() && self.iteration_status.is_some() {
+            self.clear_equation_caches();
+        }
+        self.iteration_status = status;
+    }
+
+    ///Rewrites every equation as canonical formula text, equations that cannot be parsed are left
+    ///as they are
+    pub fn format_equations(&mut self) {
+        for row_no in 0..self.rows.len() {
+            for col_no in 0..self.rows[row_no].len() {
+                let pos = Position {
+                    row: row_no,
+                    col: col_no,
+                };
+                if let Data::Equation(e, cache, _) = self.get_value_at_position(&pos) {
+                    if let Some(formatted) = calculator::format::format_equation(&e) {
+                        self.set_value_at_position(&pos, Data::equation(formatted, cache));
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear_equation_caches(&mut self) {
+        for row in self.rows.iter_mut().chain(self.columns.iter_mut()) {
+            for item in row {
+                if let Data::Equation(_, cache, _) = item {
+                    *cache = None;
+                }
+            }
+        }
+    }
+
     pub fn set_data(&mut self, rows: Vec<Vec<Data>>) {
         let columns = Table::build_columns_from_rows(&rows);
+        self.row_comments.resize(rows.len(), RowComments::default());
         self.rows = rows;
         self.columns = columns;
         let pos = self.get_pos();
@@ -179,13 +524,32 @@ impl Table {
         }
     }
 
-    pub fn get_rows(&self) -> Vec<Vec<Data>> {
-        return self.rows.clone();
+    pub fn get_state(&self) -> TableState {
+        return TableState {
+            rows: self.rows.clone(),
+            row_comments: self.row_comments.clone(),
+        };
+    }
+
+    pub fn set_state(&mut self, state: TableState) {
+        self.row_comments = state.row_comments;
+        self.set_data(state.rows);
     }
     pub fn get_pos(&self) -> Position {
         return self.current_pos;
     }
 
+    pub fn has_equations(&self) -> bool {
+        return self
+            .rows
+            .iter()
+            .any(|row| row.iter().any(|d| matches!(d, Data::Equation(..))));
+    }
+
+    pub fn get_rows(&self) -> &Vec<Vec<Data>> {
+        return &self.rows;
+    }
+
     pub fn get_size(&self) -> [usize; 2] {
         return [self.rows.len(), self.columns.len()];
     }
@@ -194,6 +558,7 @@ impl Table {
         let mut row: Vec<Data> = vec![];
         self.pad_row(&mut row);
         self.rows.insert(row_no, row);
+        self.row_comments.insert(row_no, RowComments::default());
 
         for column in &mut self.columns {
             column.insert(row_no, Data::String("".to_string()));
@@ -237,6 +602,7 @@ impl Table {
 
     pub fn remove_row(&mut self, row_no: usize) {
         self.rows.remove(row_no);
+        self.row_comments.remove(row_no);
         for col in &mut self.columns {
             col.remove(row_no);
         }
@@ -296,6 +662,27 @@ impl Table {
         self.rows[position.row][position.col] = value;
     }
 
+    ///Sets the cell at pos from text as it would be typed, text starting with `=` is an equation.
+    ///The sheet grows to hold pos, by at most MAX_GROWTH rows and columns. Returns false if pos is
+    ///further outside the sheet than that
+    pub fn set_text_at_position(&mut self, pos: &Position, text: &str) -> bool {
+        if pos.row >= self.rows.len() + MAX_GROWTH || pos.col >= self.columns.len() + MAX_GROWTH {
+            return false;
+        }
+        while self.rows.len() <= pos.row {
+            self.add_row(self.rows.len());
+        }
+        while self.columns.len() <= pos.col {
+            self.add_col(self.columns.len());
+        }
+        let data = match text.strip_prefix('=') {
+            Some(e) => Data::equation(e.to_string(), None),
+            None => self.data_from_text(text.to_string()),
+        };
+        self.set_value_at_position(pos, data);
+        return true;
+    }
+
     pub fn get_value_at_position(&self, position: &Position) -> Data {
         if position.row >= self.rows.len() {
             return Data::String("0".to_string());
@@ -307,6 +694,17 @@ impl Table {
         return row[position.col].clone();
     }
 
+    pub fn get_cells_at_range(&self, start: &Position, end: &Position) -> Vec<(Position, Data)> {
+        let mut items: Vec<(Position, Data)> = vec![];
+        for row in start.row..=end.row {
+            for col in start.col..=end.col {
+                let pos = Position { row, col };
+                items.push((pos, self.get_value_at_position(&pos)))
+            }
+        }
+        items
+    }
+
     pub fn get_values_at_range(&self, start: &Position, end: &Position) -> Vec<Data> {
         let mut items: Vec<Data> = vec![];
         for row in start.row..=end.row {
@@ -338,13 +736,13 @@ impl Table {
     pub fn remove_last_char_in_cell(&mut self, position: &Position) {
         let data = self.get_value_at_position(position);
         let new_value = match data {
-            Data::Equation(s, c) => {
+            Data::Equation(s, c, tree) => {
                 let mut new_str = s.to_owned();
                 if new_str.len() == 0 {
-                    Data::Equation(new_str, c.to_owned())
+                    Data::Equation(new_str, c.to_owned(), tree)
                 } else {
                     new_str = new_str[0..new_str.len() - 1].to_string();
-                    Data::Equation(new_str, c.to_owned())
+                    Data::equation(new_str, c.to_owned())
                 }
             }
             Data::String(s) => {
@@ -357,12 +755,12 @@ impl Table {
                 }
             }
             Data::Number(s) => {
-                let mut new_str = s.to_owned();
+                let mut new_str = self.locale.edit_number(&s);
+                new_str.pop();
                 if new_str.len() == 0 {
                     Data::String("".to_string())
                 } else {
-                    new_str = new_str[0..new_str.len() - 1].to_string();
-                    Data::Number(new_str)
+                    self.data_from_text(new_str)
                 }
             }
         };
@@ -373,27 +771,19 @@ impl Table {
         let data = self.get_value_at_position(position);
         match data {
             Data::Number(n) => {
-                let mut new_str = n.to_string();
+                let mut new_str = self.locale.edit_number(&n);
                 new_str += &String::from(char);
-                if let Ok(_) = new_str.parse::<f64>() {
-                    self.set_value_at_position(position, Data::Number(new_str));
-                } else {
-                    self.set_value_at_position(position, Data::String(new_str));
-                }
+                self.set_value_at_position(position, self.data_from_text(new_str));
             }
-            Data::Equation(s, c) => {
+            Data::Equation(s, c, _) => {
                 let mut new_str = s.to_owned();
                 new_str += &char.to_string();
-                self.set_value_at_position(position, Data::Equation(new_str, c.to_owned()))
+                self.set_value_at_position(position, Data::equation(new_str, c.to_owned()))
             }
             Data::String(s) => {
                 let mut new_str = s.to_owned();
                 new_str += &char.to_string();
-                if let Ok(_) = new_str.parse::<f64>() {
-                    self.set_value_at_position(position, Data::Number(new_str));
-                } else {
-                    self.set_value_at_position(position, Data::String(new_str))
-                }
+                self.set_value_at_position(position, self.data_from_text(new_str))
             }
         }
     }
@@ -474,15 +864,27 @@ impl Table {
 
     //TODO: accept current mode, to be able to check if the user is in insert mode, and to
     //highlight the cur char in the position
-    pub fn display(&self, max_width: usize, program: &program::Program) -> String {
+    ///Shows this sheet, which is the sheet being edited in book
+    pub fn display(&self, book: &Workbook, max_width: usize, program: &program::Program) -> String {
         let term_width = program.term_info.cols;
 
-        let mut text = format!("{:<max_width$}", " ", max_width = max_width);
+        let mut text = String::new();
+        let name = self.settings.name.as_deref().unwrap_or("");
+        let name: String = name.chars().take(max_width).collect();
+        text += &format!("{:<max_width$}", name, max_width = max_width);
         let row_slice = self.find_displayable_rows(program.term_info.lines); //TODO: make this not hardcoded
         let col_slice = self.find_displayable_cols(term_width); //TODO: make this not hardcoded
-        let mut row_no = row_slice[0];
+        //frozen rows and columns are always shown before the ones scrolled to
+        let frozen_rows = self.settings.frozen_rows.min(self.rows.len());
+        let frozen_cols = self.settings.frozen_cols.min(self.columns.len());
+        let row_nos: Vec<usize> = (0..frozen_rows)
+            .chain(row_slice[0].max(frozen_rows)..row_slice[1])
+            .collect();
+        let col_nos: Vec<usize> = (0..frozen_cols)
+            .chain(col_slice[0].max(frozen_cols)..col_slice[1])
+            .collect();
         let do_equations = !program.is_mode(program::Mode::Insert);
-        for i in col_slice[0]..col_slice[1] {
+        for &i in &col_nos {
             text += &format!(
                 "{:^max_width$}",
                 base_10_to_col_num(i + 1),
@@ -490,60 +892,105 @@ impl Table {
             );
         }
         text += &String::from("\n");
-        for row in &self.rows[row_slice[0]..row_slice[1]] {
-            let mut col_no = col_slice[0];
+        for &row_no in &row_nos {
+            let row = &self.rows[row_no];
             text += &format!(
                 "{:^max_width$}",
                 &(row_no + 1).to_string(),
                 max_width = max_width
             );
-            for item in &row[col_slice[0]..col_slice[1]] {
+            for &col_no in &col_nos {
+                let item = &row[col_no];
                 let is_selected = self.is_current_pos(row_no, col_no);
+                let pos = Position {
+                    row: row_no,
+                    col: col_no,
+                };
                 let display_text =
-                    item.display(self, self.column_sizes[col_no], do_equations, is_selected);
+                    item.display(book, &pos, self.column_sizes[col_no], do_equations, is_selected);
                 text += &(IF!(is_selected,
                     true => format!("\x1b[7m{}\x1b[0m", display_text),
                     false => display_text
                 ));
-                col_no += 1;
             }
             text += &"\n".to_owned();
-            row_no += 1;
+        }
+        if let Some(status) = self.iteration_status {
+            text += &IF!(status.converged,
+                true => format!("Converged after {} iterations\n", status.iterations),
+                false => format!("Did not converge after {} iterations\n", status.iterations)
+            );
         }
         return text;
     }
 
-    pub fn to_sheet(&self) -> String {
-        let mut text = "[".to_string();
-
-        for size in &self.column_sizes {
-            text += &(size.to_string() + &String::from(","));
+    ///The settings and rows of one sheet in the file, sheets after the first start with `%sheet`
+    pub fn sheet_block(&self, index: usize) -> String {
+        let mut text = String::new();
+        let widths: Vec<String> = self.column_sizes.iter().map(|s| s.to_string()).collect();
+        let mut directives = vec![format!("widths {}", widths.join(" "))];
+        let mut settings = self.settings.to_directives();
+        if index > 0 {
+            //the name is given by %sheet
+            settings.retain(|d| d.split_whitespace().next() != Some("name"));
+            directives.insert(
+                0,
+                match &self.settings.name {
+                    Some(name) => format!("sheet {}", name),
+                    None => "sheet".to_string(),
+                },
+            );
+        }
+        directives.append(&mut settings);
+        //comments are written above the first setting with the same name, and at the end of the
+        //settings if that setting is gone
+        let mut header_comments: Vec<&(String, Vec<String>)> = self.header_comments.iter().collect();
+        for directive in directives {
+            let name = directive.split_whitespace().next().unwrap_or("");
+            if let Some(i) = header_comments.iter().position(|c| c.0 == name) {
+                for comment in &header_comments.remove(i).1 {
+                    text += &format!("{}\n", comment);
+                }
+            }
+            text += &format!("%{}\n", directive);
+        }
+        for (_, comments) in header_comments {
+            for comment in comments {
+                text += &format!("{}\n", comment);
+            }
         }
-        text += &"]\n".to_string();
 
-        for row in &self.rows {
+        for (row, comments) in self.rows.iter().zip(&self.row_comments) {
+            for comment in &comments.above {
+                text += &format!("{}\n", comment);
+            }
             text += &String::from("[");
             for item in row {
                 match item {
                     Data::String(t) => {
-                        text += &format!("\"{}\"", t).to_string();
-                    }
-                    Data::Number(n) => {
-                        if let Ok(n) = n.parse::<f64>() {
-                            text += &format!("{}", n as f64).to_string();
-                        } else {
-                            panic!("{:?} Is not a float", n)
-                        }
+                        text += &format!("\"{}\"", sheet_tokenizer::escape_string(t));
                     }
+                    Data::Number(n) => text += n,
                     Data::Equation(t, ..) => {
-                        text += &format!("({})", t).to_string();
+                        let formatted = IF!(self.settings.format_on_save,
+                            true => calculator::format::format_equation(t),
+                            false => None
+                        );
+                        let t = formatted.as_deref().unwrap_or(t);
+                        text += &format!("({})", sheet_tokenizer::escape_expr(t));
                     }
                 }
                 text += &String::from(",");
             }
             text += &String::from("]");
+            if let Some(comment) = &comments.trailing {
+                text += &format!(" {}", comment);
+            }
             text += &String::from("\n");
         }
+        for comment in &self.footer_comments {
+            text += &format!("{}\n", comment);
+        }
         return text;
     }
 
@@ -564,8 +1011,9 @@ impl Table {
         return columns;
     }
 
+    //padded to the number of rows and columns, as a row without cells has no columns to measure
     fn pad_col(&self, col: &mut Vec<Data>) {
-        let largest_col = largest_list_in_2d_array(&self.columns);
+        let largest_col = self.rows.len();
 
         if col.len() < largest_col {
             for _ in col.len()..largest_col {
@@ -575,7 +1023,7 @@ impl Table {
     }
 
     fn pad_row(&self, row: &mut Vec<Data>) {
-        let largest_row = largest_list_in_2d_array(&self.rows);
+        let largest_row = self.columns.len();
 
         if row.len() < largest_row {
             for _ in row.len()..largest_row {
@@ -596,93 +1044,216 @@ impl Table {
         }
     }
 
-    pub fn from_csv(text: &str, seperator: char) -> Table {
-        let mut rows: Vec<Vec<Data>> = vec![];
-        let mut cur_row: Vec<Data> = vec![];
-        let mut cur_item: String = String::new();
-        for ch in text.chars() {
-            if ch == seperator {
-                if let Ok(n) = cur_item.parse::<f64>() {
-                    cur_row.push(Data::Number(n.to_string()))
-                } else {
-                    cur_row.push(Data::String(cur_item));
-                }
-                cur_item = String::new();
-            } else if ch == '\n' {
-                if let Ok(n) = cur_item.parse::<f64>() {
-                    cur_row.push(Data::Number(n.to_string()))
-                } else {
-                    cur_row.push(Data::String(cur_item));
-                }
-                rows.push(cur_row);
-                cur_row = vec![];
-                cur_item = String::new();
-            } else {
-                cur_item += &String::from(ch);
-            }
-        }
-        if let Ok(n) = cur_item.parse::<f64>() {
-            cur_row.push(Data::Number(n.to_string()))
-        } else {
-            cur_row.push(Data::String(cur_item));
-        }
-        if rows.len() > 0 {
-            if cur_row.len() == rows[0].len() + 1 {
-                //only append if it would make all rows equal size
-                rows.push(cur_row)
-            }
+    ///Creates a sheet from imported rows, short rows are padded with empty cells to the length of
+    ///the longest
+    pub fn from_rows(mut rows: Vec<Vec<Data>>, locale: Locale) -> Table {
+        if rows.len() == 0 {
+            rows.push(vec![Data::String(String::from(""))]);
         }
-        let mut column_sizes: Vec<usize> = vec![];
-        for _ in 0..rows.len() {
-            column_sizes.push(10);
+        let largest_row = largest_list_in_2d_array(&rows).max(1);
+        for row in rows.iter_mut() {
+            row.resize(largest_row, Data::String(String::from("")));
         }
         let columns = Table::build_columns_from_rows(&rows);
         Table {
+            row_comments: vec![RowComments::default(); rows.len()],
+            header_comments: vec![],
+            footer_comments: vec![],
+            included: vec![],
+            include_chain: vec![],
+            column_sizes: vec![10; columns.len()],
             rows,
-            column_sizes,
             columns,
             current_pos: Position { row: 0, col: 0 },
+            settings: Settings::default(),
+            iteration_status: None,
+            random_seed: rand::random(),
+            recalc_generation: 0,
+            locale,
         }
     }
 
-    pub fn from_sheet_tokens(toks: Vec<sheet_tokenizer::Token>) -> Table {
+    ///Loads one sheet of a file, the version is set by the file's header. Text that is not a valid
+    ///cell is loaded as a string so that the cells after it do not move
+    pub fn from_sheet_block(
+        toks: Vec<sheet_tokenizer::SheetToken>,
+        version: &mut u32,
+        errors: &mut Vec<sheet_tokenizer::LoadError>,
+    ) -> Table {
         let mut rows: Vec<Vec<Data>> = vec![];
-        let mut iter_toks = toks.into_iter();
+        let mut settings = Settings::default();
+        let mut widths: Vec<usize> = vec![];
+        let mut row_comments: Vec<RowComments> = vec![];
+        let mut header_comments: Vec<(String, Vec<String>)> = vec![];
+        //comment and blank lines that have not been attached to a row yet
+        let mut comments: Vec<String> = vec![];
+        let mut last_row_line = 0;
+        let mut iter_toks = toks.into_iter().peekable();
         type T = sheet_tokenizer::Token;
-        while let Some(T::LBracket) = iter_toks.next() {
+        let error = |tok: &sheet_tokenizer::SheetToken, message: &str| {
+            sheet_tokenizer::LoadError::new(tok, message.to_string())
+        };
+        while let Some(tok) = iter_toks.next() {
+            if let T::Directive(d) = &tok.token {
+                if comments.len() > 0 {
+                    let name = d.split_whitespace().next().unwrap_or("").to_string();
+                    header_comments.push((name, std::mem::take(&mut comments)));
+                }
+            }
+            match &tok.token {
+                T::Comment(c) => {
+                    //a comment on the same line as a row belongs to that row
+                    match row_comments.last_mut() {
+                        Some(row) if tok.line == last_row_line && row.trailing.is_none() => {
+                            row.trailing = Some(c.clone());
+                        }
+                        _ => comments.push(c.clone()),
+                    }
+                    continue;
+                }
+                T::BlankLine => {
+                    comments.push(String::new());
+                    continue;
+                }
+                T::Header(h) => {
+                    match sheet_tokenizer::parse_header(h) {
+                        Some(v) if v > sheet_tokenizer::SHEET_VERSION => {
+                            errors.push(error(&tok, &format!("tsheet {} is not supported", v)));
+                            *version = v;
+                        }
+                        Some(v) => *version = v,
+                        None => errors.push(error(&tok, &format!("Unknown header #!{}", h))),
+                    }
+                    continue;
+                }
+                T::Directive(d) if d.split_whitespace().next() == Some("widths") => {
+                    widths = vec![];
+                    for width in d.split_whitespace().skip(1) {
+                        match width.parse() {
+                            Ok(w) => widths.push(w),
+                            Err(_) => {
+                                errors.push(error(&tok, "Column sizes must be whole numbers"));
+                                widths.push(10);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                T::Directive(d) if d.split_whitespace().next() == Some("sheet") => {
+                    let name = d.trim_start()["sheet".len()..].trim();
+                    settings.name = Some(name.to_string()).filter(|n| n.len() > 0);
+                    continue;
+                }
+                T::Directive(d) => {
+                    if !settings.apply_directive(d) {
+                        errors.push(error(&tok, &format!("Unknown or invalid setting %{}", d)));
+                    }
+                    continue;
+                }
+                T::LBracket => {}
+                _ => {
+                    errors.push(error(&tok, "Expected a row starting with ["));
+                    continue;
+                }
+            }
+            row_comments.push(RowComments {
+                above: std::mem::take(&mut comments),
+                trailing: None,
+            });
             let mut current_row: Vec<Data> = vec![];
+            let mut after_value = false;
             loop {
-                let tok = iter_toks.next();
-                match tok {
-                    Some(T::RBracket) => {
+                //the row ends where the next one starts if it is not closed
+                if let Some(
+                    T::LBracket | T::Directive(_) | T::Header(_) | T::Comment(_) | T::BlankLine,
+                )
+                | None = iter_toks.peek().map(|t| &t.token)
+                {
+                    errors.push(error(&tok, "Row is not closed with ]"));
+                    rows.push(current_row);
+                    break;
+                }
+                let cell_tok = iter_toks.next().unwrap();
+                let data = match &cell_tok.token {
+                    T::RBracket => {
                         rows.push(current_row);
+                        last_row_line = cell_tok.line;
                         break;
                     }
-                    Some(T::String(text)) => {
-                        current_row.push(Data::String(text));
-                    }
-                    Some(T::Expr(text)) => {
-                        current_row.push(Data::Equation(text, None));
+                    T::Comma => {
+                        after_value = false;
+                        continue;
                     }
-                    Some(T::Number(n)) => {
-                        current_row.push(Data::Number(n.to_string()));
+                    T::String(text) => Data::String(text.clone()),
+                    T::Expr(text) => Data::equation(text.clone(), None),
+                    T::Number(n) => Data::Number(n.clone()),
+                    T::Err(text) => {
+                        let message = match text.chars().next() {
+                            Some('"') => "String is not closed".to_string(),
+                            Some('(') => "Equation is not closed".to_string(),
+                            _ => format!("{} is not a string, number or equation", text),
+                        };
+                        errors.push(error(&cell_tok, &message));
+                        Data::String(text.clone())
                     }
-                    None => break,
-                    _ => continue,
+                    T::LBracket
+                    | T::Directive(_)
+                    | T::Header(_)
+                    | T::Comment(_)
+                    | T::BlankLine => unreachable!(),
+                };
+                if after_value {
+                    errors.push(error(&cell_tok, "Expected , between cells"));
                 }
+                if *version < 2 && rows.len() == 0 && data.to_column_size().is_none() {
+                    errors.push(error(&cell_tok, "Column sizes must be whole numbers"));
+                }
+                current_row.push(data);
+                after_value = true;
             }
         }
 
-        let column_sizes = determine_column_sizes_from_rows(&mut rows);
+        //before version 2 the first row holds the column sizes
+        let mut column_sizes = if *version < 2 {
+            //the row of sizes is written as %widths
+            if rows.len() > 0 {
+                let mut sizes_comments = row_comments.remove(0);
+                sizes_comments.above.extend(sizes_comments.trailing);
+                if sizes_comments.above.len() > 0 {
+                    header_comments.push(("widths".to_string(), sizes_comments.above));
+                }
+            }
+            determine_column_sizes_from_rows(&mut rows)
+        } else {
+            if rows.len() == 0 {
+                rows.push(vec![Data::String(String::from(""))]);
+            }
+            widths
+        };
 
         let columns = Table::build_columns_from_rows(&rows);
+        if *version >= 2 {
+            column_sizes.resize(column_sizes.len().max(columns.len()), 10);
+        }
         Table::pad_rows(&mut rows);
-        return Table {
+        row_comments.resize(rows.len(), RowComments::default());
+        let table = Table {
+            row_comments,
+            header_comments,
+            footer_comments: comments,
+            included: vec![],
+            include_chain: vec![],
             rows,
             columns,
             current_pos: Position { row: 0, col: 0 },
             column_sizes,
+            settings,
+            iteration_status: None,
+            random_seed: rand::random(),
+            recalc_generation: 0,
+            locale: Locale::canonical(),
         };
+        return table;
     }
 }
 
@@ -693,13 +1264,7 @@ fn determine_column_sizes_from_rows(rows: &mut Vec<Vec<Data>>) -> Vec<usize> {
         column_sizes = rows
             .remove(0)
             .into_iter()
-            .map(|d| {
-                if let Data::Number(n) = d {
-                    let size: usize = n.parse().unwrap();
-                    return size;
-                }
-                return 10;
-            })
+            .map(|d| d.to_column_size().unwrap_or(10))
             .collect();
     } else {
         rows.push(vec![Data::String(String::from(""))]);
@@ -707,3 +1272,51 @@ fn determine_column_sizes_from_rows(rows: &mut Vec<Vec<Data>>) -> Vec<usize> {
     }
     return column_sizes;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Data, Position, Table};
+    use crate::locale::Locale;
+
+    const A1: Position = Position { row: 0, col: 0 };
+
+    fn cell(table: &Table) -> (bool, String) {
+        match table.get_value_at_position(&A1) {
+            Data::Number(n) => (true, n),
+            Data::String(s) | Data::Equation(s, ..) => (false, s),
+        }
+    }
+
+    #[test]
+    fn typing_digits_keeps_a_number() {
+        for name in ["en", "de", "fr", "ch"] {
+            let locale = Locale::from_name(name).unwrap();
+            let mut table = Table::from_rows(vec![vec![Data::String(String::new())]], locale);
+            for digit in ["1", "2", "3", "4", "5"] {
+                table.append_text_to_cell(&A1, digit.to_string());
+            }
+            assert_eq!(cell(&table), (true, "12345".to_string()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn typing_a_decimal_separator_keeps_a_number() {
+        let locale = Locale::from_name("de").unwrap();
+        let mut table = Table::from_rows(vec![vec![Data::String(String::new())]], locale);
+        for key in ["1", "2", "3", "4", ",", "5"] {
+            table.append_text_to_cell(&A1, key.to_string());
+        }
+        assert_eq!(cell(&table), (true, "1234.5".to_string()));
+    }
+
+    #[test]
+    fn backspace_on_a_grouped_number_keeps_a_number() {
+        for name in ["en", "de"] {
+            let locale = Locale::from_name(name).unwrap();
+            let rows = vec![vec![Data::Number("1234".to_string())]];
+            let mut table = Table::from_rows(rows, locale);
+            table.remove_last_char_in_cell(&A1);
+            assert_eq!(cell(&table), (true, "123".to_string()), "{}", name);
+        }
+    }
+}
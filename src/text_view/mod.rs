@@ -0,0 +1,49 @@
+///A scrollable, read only list of lines that is shown instead of the table
+pub struct TextView {
+    title: String,
+    lines: Vec<String>,
+    scroll: usize,
+}
+
+impl TextView {
+    pub fn new() -> TextView {
+        TextView {
+            title: String::new(),
+            lines: vec![],
+            scroll: 0,
+        }
+    }
+
+    pub fn set_text(&mut self, title: &str, lines: Vec<String>) {
+        self.title = title.to_string();
+        self.lines = lines;
+        self.scroll = 0;
+    }
+
+    pub fn scroll_down(&mut self, amount: usize) {
+        self.scroll += amount;
+        if self.scroll >= self.lines.len() {
+            self.scroll = self.lines.len().saturating_sub(1);
+        }
+    }
+
+    pub fn scroll_up(&mut self, amount: usize) {
+        self.scroll = self.scroll.saturating_sub(amount);
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        self.scroll = 0;
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll = self.lines.len().saturating_sub(1);
+    }
+
+    pub fn display(&self, height: usize) -> String {
+        let mut text = format!("\x1b[7m{}\x1b[0m\n", self.title);
+        for line in self.lines.iter().skip(self.scroll).take(height) {
+            text += &format!("{}\n", line);
+        }
+        return text;
+    }
+}
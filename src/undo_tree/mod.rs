@@ -1,24 +1,24 @@
-use crate::table::Data;
+use crate::table::TableState;
 
 #[derive(Clone)]
 pub struct UndoTree {
-    table_rows: Box<Vec<Vec<Data>>>,
+    state: Box<TableState>,
     previous: Option<Box<UndoTree>>,
     next: Option<Box<UndoTree>>,
     id: usize,
 }
 
 impl UndoTree {
-    pub fn new(initial_state: Box<Vec<Vec<Data>>>, id: usize) -> UndoTree {
+    pub fn new(initial_state: Box<TableState>, id: usize) -> UndoTree {
         UndoTree {
-            table_rows: initial_state,
+            state: initial_state,
             previous: None,
             next: None,
             id,
         }
     }
 
-    pub fn save(&mut self, state: Box<Vec<Vec<Data>>>) -> UndoTree {
+    pub fn save(&mut self, state: Box<TableState>) -> UndoTree {
         let mut tree = UndoTree::new(state, self.id + 1);
         self.next = Some(Box::new(tree.clone()));
         tree.previous = Some(Box::new(self.clone()));
@@ -33,8 +33,8 @@ impl UndoTree {
         return self.next.clone();
     }
 
-    pub fn get_state(&self) -> Box<Vec<Vec<Data>>> {
-        return self.table_rows.clone();
+    pub fn get_state(&self) -> Box<TableState> {
+        return self.state.clone();
     }
 
     pub fn get_id(&self) -> usize{
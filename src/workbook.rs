@@ -0,0 +1,359 @@
+use std::path::PathBuf;
+
+use crate::{
+    calculator::{self, calculate_cell, CalculatorError, CalculatorValue},
+    locale::Locale,
+    program,
+    settings::IterationStatus,
+    sheet_tokenizer,
+    table::{format_value, Data, Position, Table},
+};
+
+///The sheets saved in one file, one of which is being edited
+#[derive(Debug)]
+pub struct Workbook {
+    ///Every sheet, in the order they are saved
+    sheets: Vec<Table>,
+    ///Where the sheet being edited is in sheets
+    active: usize,
+}
+
+///A sheet that equations can reference by name, as in `name!$A1`
+pub enum FoundSheet<'a> {
+    ///The index of a sheet in the same file
+    Workbook(usize),
+    ///The file loaded by `%include`, its first sheet is the one referenced
+    Include(&'a Workbook),
+}
+
+///How much a value changed between two iterations, infinite if it changed type
+fn value_change(old: &Option<CalculatorValue>, new: &Option<CalculatorValue>) -> f64 {
+    return match (old, new) {
+        (Some(CalculatorValue::Number(a)), Some(CalculatorValue::Number(b))) => (a - b).abs(),
+        (Some(CalculatorValue::Decimal(a)), Some(CalculatorValue::Decimal(b))) => {
+            (a.to_f64() - b.to_f64()).abs()
+        }
+        (None, None) => 0.0,
+        (Some(a), Some(b)) if format_value(a) == format_value(b) => 0.0,
+        _ => f64::INFINITY,
+    };
+}
+
+impl Workbook {
+    ///A file with one sheet
+    pub fn new(sheet: Table) -> Workbook {
+        return Workbook {
+            sheets: vec![sheet],
+            active: 0,
+        };
+    }
+
+    ///Loads as much of the file as possible, problems are returned instead of stopping the load
+    pub fn from_sheet_tokens(
+        toks: Vec<sheet_tokenizer::SheetToken>,
+    ) -> (Workbook, Vec<sheet_tokenizer::LoadError>) {
+        type T = sheet_tokenizer::Token;
+        //each %sheet starts another sheet, which the comments above it belong to
+        let mut blocks: Vec<Vec<sheet_tokenizer::SheetToken>> = vec![vec![]];
+        for tok in toks {
+            if let T::Directive(d) = &tok.token {
+                if d.split_whitespace().next() == Some("sheet") {
+                    let is_first = blocks.len() == 1;
+                    let block = blocks.last_mut().unwrap();
+                    let mut start = block.len();
+                    while start > 0
+                        && matches!(block[start - 1].token, T::Comment(_) | T::BlankLine)
+                        && (start < 2 || block[start - 2].line != block[start - 1].line)
+                    {
+                        start -= 1;
+                    }
+                    let comments = block.split_off(start);
+                    //a file can start with %sheet
+                    let is_empty =
+                        is_first && block.iter().all(|t| matches!(t.token, T::Header(_)));
+                    if is_empty {
+                        block.extend(comments);
+                    } else {
+                        blocks.push(comments);
+                    }
+                }
+            }
+            blocks.last_mut().unwrap().push(tok);
+        }
+
+        let mut errors: Vec<sheet_tokenizer::LoadError> = vec![];
+        let mut version = 1;
+        let sheets: Vec<Table> = blocks
+            .into_iter()
+            .map(|block| Table::from_sheet_block(block, &mut version, &mut errors))
+            .collect();
+        return (Workbook { sheets, active: 0 }, errors);
+    }
+
+    pub fn to_sheet(&self) -> String {
+        let mut text = format!("#!tsheet {}\n", sheet_tokenizer::SHEET_VERSION);
+        for (index, sheet) in self.sheets.iter().enumerate() {
+            text += &sheet.sheet_block(index);
+        }
+        return text;
+    }
+
+    ///The number of sheets in the file
+    pub fn len(&self) -> usize {
+        return self.sheets.len();
+    }
+
+    ///Where the sheet being edited is in the file
+    pub fn active_index(&self) -> usize {
+        return self.active;
+    }
+
+    ///The sheet being edited
+    pub fn table(&self) -> &Table {
+        return &self.sheets[self.active];
+    }
+
+    pub fn table_mut(&mut self) -> &mut Table {
+        return &mut self.sheets[self.active];
+    }
+
+    pub fn sheet(&self, index: usize) -> &Table {
+        return &self.sheets[index];
+    }
+
+    ///The sheet's `%name`, or its position as in Sheet2
+    pub fn sheet_name(&self, index: usize) -> String {
+        return match &self.sheets[index].get_settings().name {
+            Some(name) => name.clone(),
+            None => format!("Sheet{}", index + 1),
+        };
+    }
+
+    ///Where the sheet called name is in the file
+    pub fn find_index(&self, name: &str) -> Option<usize> {
+        return (0..self.len()).find(|i| self.sheet_name(*i).eq_ignore_ascii_case(name));
+    }
+
+    ///Finds a sheet that equations in the sheet at index `from` can reference as `name!$A1`.
+    ///Sheets in the same file are found before included sheets
+    pub fn find_sheet(&self, name: &str, from: usize) -> Option<FoundSheet<'_>> {
+        if let Some(index) = self.find_index(name) {
+            return Some(FoundSheet::Workbook(index));
+        }
+        return self.sheets[from]
+            .find_included(name)
+            .map(FoundSheet::Include);
+    }
+
+    ///Makes the sheet at index the one being edited, returns false if there is no other sheet at
+    ///index
+    pub fn switch(&mut self, index: usize) -> bool {
+        if index >= self.len() || index == self.active {
+            return false;
+        }
+        self.active = index;
+        return true;
+    }
+
+    ///Adds an empty sheet at the end of the file, returns its index
+    pub fn add_sheet(&mut self, name: &str) -> usize {
+        let mut sheet = Table::from_rows(vec![], Locale::canonical());
+        sheet.get_settings_mut().name = Some(name.to_string());
+        return self.push_sheet(sheet);
+    }
+
+    ///Adds a loaded sheet at the end of the file, returns its index
+    pub fn push_sheet(&mut self, mut sheet: Table) -> usize {
+        sheet.join_file(&self.sheets[0]);
+        self.sheets.push(sheet);
+        return self.len() - 1;
+    }
+
+    ///The result of the equation at pos in the sheet at index, in iterative mode the result of
+    ///the last iteration. None if it is not an equation
+    pub fn evaluate_in_sheet(
+        &self,
+        index: usize,
+        pos: &Position,
+    ) -> Option<Result<CalculatorValue, CalculatorError>> {
+        let sheet = &self.sheets[index];
+        match sheet.get_value_at_position(pos) {
+            Data::Equation(_, Some(v), _) if sheet.is_iterative() => return Some(Ok(v)),
+            Data::Equation(_, _, tree) => {
+                let mut map = calculator::sheet_cell_symbols(pos, index);
+                return Some(calculate_cell(pos, &tree, &mut map, self, None));
+            }
+            _ => return None,
+        }
+    }
+
+    ///Evaluates the cell at pos in the sheet being edited, returning every step of the evaluation
+    pub fn explain(&self, pos: &Position) -> Vec<String> {
+        let name = crate::table::position_name(pos);
+        match self.table().get_value_at_position(pos) {
+            Data::Equation(e, _, tree) => {
+                let mut trace = calculator::trace::Trace::new();
+                let mut map = calculator::sheet_cell_symbols(pos, self.active);
+                trace.record(&format!("{}: ({})", name, e));
+                let _ = calculate_cell(pos, &tree, &mut map, self, Some(&mut trace));
+                return trace.get_lines();
+            }
+            Data::Number(n) | Data::String(n) => {
+                return vec![format!("{}: {} is not an equation", name, n)];
+            }
+        }
+    }
+
+    pub fn set_locale(&mut self, locale: Locale) {
+        for sheet in self.sheets.iter_mut() {
+            sheet.set_locale(locale);
+        }
+    }
+
+    ///Recalculates volatile functions in every sheet
+    pub fn recalc_volatile(&mut self) {
+        for sheet in self.sheets.iter_mut() {
+            sheet.recalc_volatile();
+        }
+    }
+
+    ///Loads the files named by `%include` in every sheet, chain ends with this file, and starts
+    ///with the files that include it. Returns the problems found
+    pub fn load_includes(&mut self, chain: Vec<PathBuf>) -> Vec<String> {
+        let mut errors: Vec<String> = vec![];
+        for sheet in self.sheets.iter_mut() {
+            errors.append(&mut sheet.load_includes(chain.clone()));
+        }
+        return errors;
+    }
+
+    ///Loads included files that were added or have changed since they were loaded
+    pub fn reload_includes(&mut self) -> Vec<String> {
+        let mut errors: Vec<String> = vec![];
+        for sheet in self.sheets.iter_mut() {
+            errors.append(&mut sheet.reload_includes());
+        }
+        return errors;
+    }
+
+    ///Evaluates every equation in the sheets in iterative mode repeatedly, using the previous
+    ///results for references, until the values stop changing by more than epsilon or the
+    ///iteration limit is reached. Results are stored in each equation's cache. The sheets are
+    ///iterated together so that references between them settle as well
+    pub fn recalculate(&mut self) {
+        let mut equations: Vec<(usize, Position)> = vec![];
+        let mut max_iterations = 0;
+        let mut epsilon = f64::INFINITY;
+        for (index, sheet) in self.sheets.iter_mut().enumerate() {
+            match sheet.get_settings().iteration {
+                None => sheet.set_iteration_status(None),
+                Some(iteration) => {
+                    max_iterations = max_iterations.max(iteration.max_iterations);
+                    epsilon = epsilon.min(iteration.epsilon);
+                    for pos in sheet.equation_positions() {
+                        equations.push((index, pos));
+                    }
+                }
+            }
+        }
+        if max_iterations == 0 {
+            return;
+        }
+
+        let mut status = IterationStatus {
+            iterations: max_iterations,
+            converged: false,
+        };
+        for i in 1..=max_iterations {
+            let mut largest_change: f64 = 0.0;
+            for (index, pos) in &equations {
+                let (text, old, tree) = match self.sheets[*index].get_value_at_position(pos) {
+                    Data::Equation(e, cache, tree) => (e, cache, tree),
+                    _ => continue,
+                };
+                let mut map = calculator::sheet_cell_symbols(pos, *index);
+                let new = calculate_cell(pos, &tree, &mut map, self, None).ok();
+                let change = value_change(&old, &new);
+                if change > largest_change || change.is_nan() {
+                    largest_change = change;
+                }
+                self.sheets[*index].set_value_at_position(pos, Data::Equation(text, new, tree));
+            }
+            if largest_change <= epsilon {
+                status = IterationStatus {
+                    iterations: i,
+                    converged: true,
+                };
+                break;
+            }
+        }
+        for sheet in self.sheets.iter_mut() {
+            if sheet.is_iterative() {
+                sheet.set_iteration_status(Some(status));
+            }
+        }
+    }
+
+    ///Shows the sheet being edited, with the names of the sheets above it if there are several
+    pub fn display(&self, max_width: usize, program: &program::Program) -> String {
+        let mut text = String::new();
+        //the sheets in the file, with the one being edited in []
+        if self.len() > 1 {
+            let tabs: Vec<String> = (0..self.len())
+                .map(|i| {
+                    if i == self.active {
+                        return format!("[{}]", self.sheet_name(i));
+                    }
+                    return self.sheet_name(i);
+                })
+                .collect();
+            text += &format!("{}\n", tabs.join(" "));
+        }
+        text += &self.table().display(self, max_width, program);
+        return text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table;
+
+    fn book(text: &str) -> Workbook {
+        let (mut book, errors) = Workbook::from_sheet_tokens(sheet_tokenizer::parse(text));
+        assert!(errors.is_empty(), "{:?}", errors);
+        book.recalculate();
+        return book;
+    }
+
+    fn value(book: &Workbook, index: usize, cell: &str) -> f64 {
+        let pos = table::parse_position_name(cell).unwrap();
+        return match book.evaluate_in_sheet(index, &pos) {
+            Some(Ok(CalculatorValue::Number(n))) => n,
+            other => panic!("{} is {:?}", cell, other),
+        };
+    }
+
+    #[test]
+    fn iterates_circular_references_until_they_settle() {
+        let book = book("#!tsheet 2\n%iterate 100 0.0001\n[($B1 / 2 + 1),($A1)]\n");
+        assert!((value(&book, 0, "$A1") - 2.0).abs() < 0.001);
+        assert!((value(&book, 0, "$B1") - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn stops_at_the_iteration_limit() {
+        let book = book("#!tsheet 2\n%iterate 5\n[($A1 + 1),]\n");
+        assert_eq!(value(&book, 0, "$A1"), 5.0);
+    }
+
+    #[test]
+    fn iterates_sheets_together() {
+        let book = book(
+            "#!tsheet 2\n%iterate 100 0.0001\n[(Other!$A1 / 2 + 1),]\n\n\
+             %sheet Other\n%iterate 100 0.0001\n[(Sheet1!$A1),]\n",
+        );
+        assert!((value(&book, 0, "$A1") - 2.0).abs() < 0.001);
+        assert!((value(&book, 1, "$A1") - 2.0).abs() < 0.001);
+    }
+}